@@ -0,0 +1,280 @@
+// Fake players, driven by issuing PlayerCommands like a client would. Bots only look at the
+// State, so they behave the same wherever they run.
+
+use {State, Player, IdPlayerCommand, PlayerCommand};
+use replay::Input;
+
+// How far around itself a bot notices things, on top of its own size
+const SIGHT: f64 = 80.;
+const SPEED: f64 = 6.;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Strategy {
+    // Goes for the closest ball
+    PelletGreedy,
+    // Runs from everything that can eat it, eating balls when nothing is around
+    FleeBigger,
+    // Chases the closest cell it can eat, eating balls when nothing is around
+    HuntSmaller,
+    // All of the above, each pulling with its weight
+    Mixed { pellets: f64, flee: f64, hunt: f64 },
+}
+
+impl Strategy {
+    // What new bots get, in turn
+    pub fn all() -> Vec<Strategy> {
+        vec![
+            Strategy::PelletGreedy,
+            Strategy::FleeBigger,
+            Strategy::HuntSmaller,
+            Strategy::Mixed { pellets: 1., flee: 3., hunt: 2. },
+        ]
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Bot {
+    pub id: usize,
+    pub strategy: Strategy,
+}
+
+impl Bot {
+    // The command the bot wants to send now. Bots that were eaten respawn.
+    pub fn think(&self, state: &State) -> Option<IdPlayerCommand> {
+        let me = match state.players.get(&self.id) {
+            Some(me) => me,
+            None => return Some(IdPlayerCommand { id: self.id, command: PlayerCommand::Respawn }),
+        };
+        let pos = me.center(&state.config);
+
+        let or_pellets = |way: (f64, f64)| if way == (0., 0.) { pellets(state, me, pos) } else { way };
+
+        let (dx, dy) = match self.strategy {
+            Strategy::PelletGreedy => pellets(state, me, pos),
+            Strategy::FleeBigger => or_pellets(flee(state, self.id, me, pos)),
+            Strategy::HuntSmaller => or_pellets(hunt(state, self.id, me, pos)),
+            Strategy::Mixed { pellets: wp, flee: wf, hunt: wh } => {
+                let parts = [
+                    (wp, pellets(state, me, pos)),
+                    (wf, flee(state, self.id, me, pos)),
+                    (wh, hunt(state, self.id, me, pos)),
+                ];
+                let mut sum = (0., 0.);
+                for (weight, (x, y)) in parts.iter() {
+                    sum.0 += weight * x;
+                    sum.1 += weight * y;
+                }
+                sum
+            }
+        };
+
+        if dx == 0. && dy == 0. {
+            return None;
+        }
+        Some(IdPlayerCommand { id: self.id, command: PlayerCommand::SetDirectionAndSpeed(dx.atan2(dy), SPEED) })
+    }
+}
+
+fn normalized(v: (f64, f64)) -> (f64, f64) {
+    let len = (v.0 * v.0 + v.1 * v.1).sqrt();
+    if len == 0. { (0., 0.) } else { (v.0 / len, v.1 / len) }
+}
+
+// The way to the closest ball in sight, of length 1
+fn pellets(state: &State, me: &Player, pos: (f64, f64)) -> (f64, f64) {
+    let sight = SIGHT + me.size();
+
+    let mut closest = None;
+    for ball in &state.balls {
+        let (dx, dy) = state.config.delta(pos, ball.pos);
+        let dist = (dx * dx + dy * dy).sqrt();
+        if dist < sight && closest.map(|(d, _)| dist < d).unwrap_or(true) {
+            closest = Some((dist, (dx, dy)));
+        }
+    }
+    closest.map(|(_, to)| normalized(to)).unwrap_or((0., 0.))
+}
+
+// Away from every cell in sight that could eat one of ours, closer ones counting more
+fn flee(state: &State, my_id: usize, me: &Player, pos: (f64, f64)) -> (f64, f64) {
+    let sight = SIGHT + me.size();
+    let smallest = me.cells.iter().map(|cell| cell.size).fold(f64::INFINITY, f64::min);
+
+    let mut away = (0., 0.);
+    for (id, player) in &state.players {
+        if *id == my_id { continue }
+        for cell in &player.cells {
+            if cell.size < smallest * state.config.size_ratio_to_eat { continue }
+
+            let (dx, dy) = state.config.delta(pos, cell.pos);
+            let dist = (dx * dx + dy * dy).sqrt();
+            if dist < sight + cell.size && dist > 0. {
+                away.0 -= dx / (dist * dist);
+                away.1 -= dy / (dist * dist);
+            }
+        }
+    }
+    normalized(away)
+}
+
+// The way to the closest cell in sight that our biggest cell can eat, of length 1
+fn hunt(state: &State, my_id: usize, me: &Player, pos: (f64, f64)) -> (f64, f64) {
+    let sight = SIGHT + me.size();
+    let biggest = me.cells.iter().map(|cell| cell.size).fold(0., f64::max);
+
+    let mut closest = None;
+    for (id, player) in &state.players {
+        if *id == my_id { continue }
+        for cell in &player.cells {
+            if cell.size >= biggest / state.config.size_ratio_to_eat { continue }
+
+            let (dx, dy) = state.config.delta(pos, cell.pos);
+            let dist = (dx * dx + dy * dy).sqrt();
+            if dist < sight && closest.map(|(d, _)| dist < d).unwrap_or(true) {
+                closest = Some((dist, (dx, dy)));
+            }
+        }
+    }
+    closest.map(|(_, to)| normalized(to)).unwrap_or((0., 0.))
+}
+
+// Keeps a game at a minimum number of players by adding bots, which step aside again as
+// other players join
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Bots {
+    pub bots: Vec<Bot>,
+    added: usize, // For picking strategies and names
+}
+
+impl Bots {
+    pub fn new() -> Bots {
+        Bots::default()
+    }
+
+    pub fn is_bot(&self, id: usize) -> bool {
+        self.bots.iter().any(|bot| bot.id == id)
+    }
+
+    // Everyone who joined counts, whether they are alive or not. new_id gives an id for a
+    // new bot that nobody else uses. Returns the inputs that were applied, for recording.
+    pub fn keep_population<F: FnMut(&State) -> usize>(&mut self, state: &mut State, min_population: usize, mut new_id: F) -> Vec<Input> {
+        let humans = state.names.keys().filter(|id| !self.is_bot(**id)).count();
+        let mut applied = vec![];
+
+        while humans + self.bots.len() < min_population {
+            let strategies = Strategy::all();
+            let strategy = strategies[self.added % strategies.len()].clone();
+            let id = new_id(state);
+
+            self.added += 1;
+            let join = Input::Join { id, name: format!("Bot {}", self.added) };
+            join.clone().apply(state);
+            applied.push(join);
+            self.bots.push(Bot { id, strategy });
+        }
+
+        // The newest bots leave first
+        while humans + self.bots.len() > min_population {
+            match self.bots.pop() {
+                Some(bot) => {
+                    let leave = Input::Leave { id: bot.id };
+                    leave.clone().apply(state);
+                    applied.push(leave);
+                }
+                None => break,
+            }
+        }
+        applied
+    }
+
+    // Returns the inputs that were applied, for recording
+    pub fn command_all(&self, state: &mut State) -> Vec<Input> {
+        let mut applied = vec![];
+        for bot in &self.bots {
+            if let Some(command) = bot.think(state) {
+                let input = Input::Command(command);
+                input.clone().apply(state);
+                applied.push(input);
+            }
+        }
+        applied
+    }
+}
+
+#[cfg(test)]
+use Cell;
+
+#[cfg(test)]
+fn bot_direction(state: &State, strategy: Strategy) -> (f64, f64) {
+    match (Bot { id: 1, strategy }).think(state).map(|command| command.command) {
+        Some(PlayerCommand::SetDirectionAndSpeed(dir, _)) => (dir.sin(), dir.cos()),
+        other => panic!("Expected a direction, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_strategies() {
+    use Ball;
+
+    let player = |pos, size| Player { cells: vec![Cell::new(pos, size)], direction: 0., speed: 0., color: (0, 0, 0) };
+
+    let mut state = State::with_seed(0);
+    state.players.insert(1, player((500., 500.), 10.));
+    state.players.insert(2, player((540., 500.), 20.)); // Can eat us, to the right
+    state.players.insert(3, player((500., 450.), 5.)); // We can eat, above
+    state.balls.push(Ball { id: 0, pos: (500., 530.), color: (0, 0, 0), size: 1., value: 3. }); // Below
+
+    let close = |a: (f64, f64), b: (f64, f64)| (a.0 - b.0).abs() < 1e-9 && (a.1 - b.1).abs() < 1e-9;
+    assert!(close(bot_direction(&state, Strategy::PelletGreedy), (0., 1.)));
+    assert!(close(bot_direction(&state, Strategy::FleeBigger), (-1., 0.)));
+    assert!(close(bot_direction(&state, Strategy::HuntSmaller), (0., -1.)));
+
+    // Fleeing outweighs the rest
+    let mixed = bot_direction(&state, Strategy::Mixed { pellets: 1., flee: 5., hunt: 1. });
+    assert!(mixed.0 < -0.9);
+
+    // Eaten bots come back
+    state.players.remove(&1);
+    assert_eq!((Bot { id: 1, strategy: Strategy::PelletGreedy }).think(&state),
+               Some(IdPlayerCommand { id: 1, command: PlayerCommand::Respawn }));
+}
+
+#[test]
+fn test_bots_step_aside() {
+    let mut state = State::with_seed(0);
+    let mut bots = Bots::new();
+    let free_id = |state: &State| (100..).find(|id| !state.names.contains_key(id)).unwrap();
+
+    bots.keep_population(&mut state, 5, free_id);
+    assert_eq!(bots.bots.len(), 5);
+    assert_eq!(state.players.len(), 5);
+
+    state.join(1, "human".to_string());
+    state.join(2, "another".to_string());
+    bots.keep_population(&mut state, 5, free_id);
+    assert_eq!(bots.bots.len(), 3);
+    assert_eq!(state.players.len(), 5);
+    assert!(state.players.contains_key(&1) && state.players.contains_key(&2));
+
+    state.remove_player(1);
+    bots.keep_population(&mut state, 5, free_id);
+    assert_eq!(bots.bots.len(), 4);
+    assert_eq!(state.names.len(), 5);
+}
+
+#[test]
+fn test_greedy_bots_grow() {
+    let mut state = State::with_seed(4);
+    state.config.virus_count = 0;
+    state.fill_balls();
+
+    let bots = Bots { bots: vec![Bot { id: 1, strategy: Strategy::PelletGreedy }], added: 0 };
+    state.join(1, "greedy".to_string());
+    let start = state.players[&1].mass();
+
+    for _ in 0..20 * 64 {
+        bots.command_all(&mut state);
+        state.tick(::TICK_DT);
+    }
+    assert!(state.players[&1].mass() > start * 2.);
+}
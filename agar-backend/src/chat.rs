@@ -0,0 +1,87 @@
+// Chat between players. Messages don't touch the State, the server just passes them on to
+// every client together with the updates.
+
+pub const MAX_CHAT_LENGTH: usize = 120;
+
+// How many messages can be sent at once, and how fast that allowance comes back
+pub const CHAT_BURST: f64 = 5.;
+pub const CHAT_PER_SEC: f64 = 0.5;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ChatMessage {
+    pub id: usize,
+    pub name: String,
+    pub text: String,
+}
+
+// Returns the message with surrounding whitespace removed and control characters replaced
+// by spaces, or None if it's empty or too long
+pub fn clean_message(text: &str) -> Option<String> {
+    let text: String = text.trim()
+        .chars()
+        .map(|ch| if ch.is_control() { ' ' } else { ch })
+        .collect();
+
+    if text.is_empty() || text.chars().count() > MAX_CHAT_LENGTH {
+        None
+    } else {
+        Some(text)
+    }
+}
+
+// A token bucket, one per connection. Times are in seconds from any fixed point.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    tokens: f64,
+    burst: f64,
+    per_sec: f64,
+    last: f64,
+}
+
+impl RateLimiter {
+    pub fn new(burst: f64, per_sec: f64, now: f64) -> RateLimiter {
+        RateLimiter { tokens: burst, burst, per_sec, last: now }
+    }
+
+    // Whether a message sent at `now` is let through
+    pub fn allow(&mut self, now: f64) -> bool {
+        self.tokens = (self.tokens + (now - self.last) * self.per_sec).min(self.burst);
+        self.last = now;
+
+        if self.tokens >= 1. {
+            self.tokens -= 1.;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[test]
+fn test_clean_message() {
+    assert_eq!(clean_message("  hi there "), Some("hi there".to_string()));
+    assert_eq!(clean_message("a\nb"), Some("a b".to_string()));
+    assert_eq!(clean_message("   "), None);
+    assert_eq!(clean_message(&"x".repeat(MAX_CHAT_LENGTH)), Some("x".repeat(MAX_CHAT_LENGTH)));
+    assert_eq!(clean_message(&"x".repeat(MAX_CHAT_LENGTH + 1)), None);
+}
+
+#[test]
+fn test_rate_limiter() {
+    let mut limiter = RateLimiter::new(3., 1., 0.);
+
+    // The burst goes through, then nothing until the bucket refills
+    assert!(limiter.allow(0.));
+    assert!(limiter.allow(0.));
+    assert!(limiter.allow(0.1));
+    assert!(!limiter.allow(0.2));
+    assert!(!limiter.allow(0.5));
+    assert!(limiter.allow(1.3));
+    assert!(!limiter.allow(1.4));
+
+    // Waiting long doesn't give more than the burst
+    for _ in 0..3 {
+        assert!(limiter.allow(100.));
+    }
+    assert!(!limiter.allow(100.));
+}
@@ -0,0 +1,159 @@
+// All tuning values of the game. The server loads these from a file and the clients get
+// them as part of the State, so both sides simulate with the same numbers.
+//
+// Missing fields when deserializing are taken from the defaults.
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct GameConfig {
+    pub world_size: (f64, f64),
+    pub world_mode: WorldMode,
+
+    // Players
+    pub start_size: f64,
+    pub speed_factor: f64,
+    pub grow_speed: f64, // How fast the drawn size catches up with the real size
+    pub size_ratio_to_eat: f64,
+
+    // Balls. They are spawned until there are ball_density per unit area, but never more
+    // than max_balls. Every pellet eaten in about the last ball_eaten_window seconds speeds
+    // spawning up, so eaten balls come back about as fast as they go.
+    pub ball_density: f64,
+    pub max_balls: usize,
+    pub ball_spawn_per_sec: f64,
+    pub ball_eaten_window: f64,
+    pub ball_kinds: Vec<BallKind>,
+
+    // Splitting
+    pub min_split_size: f64,
+    pub max_cells: usize,
+    pub split_speed: f64,
+    pub split_friction: f64,
+    pub merge_time: f64, // Seconds until a split cell can merge back
+
+    // Ejecting mass
+    pub min_eject_size: f64,
+    pub eject_area_loss: f64, // In size^2, what the ejecting cell loses
+    pub eject_area: f64, // In size^2, what the pellet is worth
+    pub eject_speed: f64,
+    pub eject_friction: f64,
+
+    // Viruses
+    pub virus_count: usize,
+    pub virus_size: f64,
+    pub virus_max_size: f64, // When fed to this size, the virus shoots off a new one
+    pub virus_speed: f64,
+    pub virus_friction: f64,
+    pub virus_pop_pieces: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum WorldMode {
+    // Everything is kept inside the world
+    Walled,
+    // Leaving one edge brings you in on the opposite one. Obstacles crossing an edge don't
+    // work in this mode.
+    Wrapped,
+}
+
+// A sort of ball that can be spawned, picked with a probability proportional to its weight
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BallKind {
+    pub size: f64,
+    pub value: f64, // In size^2, what eating it is worth
+    pub weight: f64,
+}
+
+impl Default for GameConfig {
+    fn default() -> GameConfig {
+        GameConfig {
+            world_size: (1000., 1000.),
+            world_mode: WorldMode::Walled,
+
+            start_size: 3.,
+            speed_factor: 35.,
+            grow_speed: 4.,
+            size_ratio_to_eat: 1.2,
+
+            ball_density: 0.0002,
+            max_balls: 2000,
+            ball_spawn_per_sec: 1.,
+            ball_eaten_window: 10.,
+            ball_kinds: vec![
+                BallKind { size: 1., value: 3., weight: 10. },
+                BallKind { size: 2., value: 12., weight: 1. },
+            ],
+
+            min_split_size: 6.,
+            max_cells: 16,
+            split_speed: 60.,
+            split_friction: 20.,
+            merge_time: 10.,
+
+            min_eject_size: 5.,
+            eject_area_loss: 16.,
+            eject_area: 12.,
+            eject_speed: 80.,
+            eject_friction: 10.,
+
+            virus_count: 10,
+            virus_size: 10.,
+            virus_max_size: 14.,
+            virus_speed: 80.,
+            virus_friction: 10.,
+            virus_pop_pieces: 8,
+        }
+    }
+}
+
+impl GameConfig {
+    // The shortest way from one position to another, which might cross an edge when the
+    // world wraps
+    pub fn delta(&self, from: (f64, f64), to: (f64, f64)) -> (f64, f64) {
+        let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+        match self.world_mode {
+            WorldMode::Walled => (dx, dy),
+            WorldMode::Wrapped => (wrap_delta(dx, self.world_size.0), wrap_delta(dy, self.world_size.1)),
+        }
+    }
+
+    // Brings a position that left the world back in, through the opposite edge if the world
+    // wraps. Walls keep everything at least margin inside.
+    pub fn keep_inside(&self, pos: (f64, f64), margin: f64) -> (f64, f64) {
+        let world = self.world_size;
+        match self.world_mode {
+            WorldMode::Walled => (
+                pos.0.max(margin).min(world.0 - margin),
+                pos.1.max(margin).min(world.1 - margin),
+            ),
+            WorldMode::Wrapped => (pos.0.rem_euclid(world.0), pos.1.rem_euclid(world.1)),
+        }
+    }
+}
+
+// d moved into [-size / 2, size / 2)
+fn wrap_delta(d: f64, size: f64) -> f64 {
+    (d + size / 2.).rem_euclid(size) - size / 2.
+}
+
+#[test]
+fn test_wrapped_distances() {
+    let mut config = GameConfig { world_size: (100., 50.), ..GameConfig::default() };
+
+    assert_eq!(config.delta((95., 5.), (5., 45.)), (-90., 40.));
+    assert_eq!(config.keep_inside((-3., 60.), 2.), (2., 48.));
+
+    config.world_mode = WorldMode::Wrapped;
+    assert_eq!(config.delta((95., 5.), (5., 45.)), (10., -10.));
+    assert_eq!(config.delta((5., 45.), (95., 5.)), (-10., 10.));
+    assert_eq!(config.keep_inside((-3., 60.), 2.), (97., 10.));
+}
+
+#[test]
+fn test_missing_fields_are_defaults() {
+    let config: GameConfig = ::serde_json::from_str(r#"{ "world_size": [200, 300], "max_cells": 4 }"#).unwrap();
+
+    assert_eq!(config.world_size, (200., 300.));
+    assert_eq!(config.max_cells, 4);
+    assert_eq!(config.merge_time, GameConfig::default().merge_time);
+}
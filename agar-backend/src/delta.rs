@@ -0,0 +1,309 @@
+// Sending clients only what changed. The server remembers the states it sent to a client, and
+// the client says which ones it got. Each update is then the difference to the newest state
+// the client has acknowledged: players that moved or changed, balls created and removed, and
+// the rest only when it changed. Every KEYFRAME_EVERY updates, or when the client hasn't
+// acknowledged anything the server still has, the whole state is sent instead. The config and
+// obstacles hardly ever change, so keyframes only have them when the client doesn't have
+// them yet.
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+use {State, Player, Ball, EjectedMass, Virus, GameConfig, Obstacle, Rng};
+
+pub const KEYFRAME_EVERY: usize = 50;
+// States remembered on either side. Clients that haven't acknowledged any of them get a
+// keyframe.
+const HISTORY: usize = 16;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Update {
+    Keyframe(Keyframe),
+    Delta(Delta),
+}
+
+impl Update {
+    // The ticks of the state the client has to have to make sense of this
+    pub fn base(&self) -> Option<u64> {
+        match *self {
+            Update::Keyframe(ref keyframe) => keyframe.base,
+            Update::Delta(ref delta) => Some(delta.base),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Keyframe {
+    pub state: State,
+    // Whether the config and obstacles are sent along. If not, the client's newest state
+    // already has the same ones.
+    pub world: bool,
+    // The ticks of the newest state the client acknowledged, if the server still has it. The
+    // client has it too, so what's in it can go without its colour, see protocol.
+    pub base: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Delta {
+    pub base: u64, // The ticks of the state this is relative to
+    pub ticks: u64,
+    // New or changed
+    pub players: BTreeMap<usize, Player>,
+    pub removed_players: Vec<usize>,
+    pub names: BTreeMap<usize, String>,
+    pub removed_names: Vec<usize>,
+    pub created_balls: Vec<Ball>,
+    pub removed_balls: Vec<u64>,
+    // Everything else that can change, when it did
+    pub ejected: Option<Vec<EjectedMass>>,
+    pub viruses: Option<Vec<Virus>>,
+    pub eaten_by: Option<BTreeMap<usize, usize>>,
+    pub config: Option<GameConfig>,
+    pub obstacles: Option<Vec<Obstacle>>,
+    pub rng: Rng,
+    pub next_ball_id: u64,
+    pub accumulator: f64,
+    pub recent_balls_eaten: f64,
+}
+
+fn changed<T: Clone + PartialEq>(base: &T, now: &T) -> Option<T> {
+    if base == now { None } else { Some(now.clone()) }
+}
+
+fn map_changes<K: Ord + Copy, V: Clone + PartialEq>(base: &BTreeMap<K, V>, now: &BTreeMap<K, V>) -> (BTreeMap<K, V>, Vec<K>) {
+    let changed = now.iter()
+        .filter(|(k, v)| base.get(k) != Some(v))
+        .map(|(k, v)| (*k, v.clone()))
+        .collect();
+    let removed = base.keys().filter(|k| !now.contains_key(k)).cloned().collect();
+    (changed, removed)
+}
+
+impl Delta {
+    pub fn between(base: &State, state: &State) -> Delta {
+        let (players, removed_players) = map_changes(&base.players, &state.players);
+        let (names, removed_names) = map_changes(&base.names, &state.names);
+
+        // Balls never change, they only appear and disappear. Not just by spawning and being
+        // eaten, but also by coming into and leaving the view, see view::visible.
+        let base_ids: BTreeSet<u64> = base.balls.iter().map(|ball| ball.id).collect();
+        let ball_ids: BTreeSet<u64> = state.balls.iter().map(|ball| ball.id).collect();
+        let created_balls = state.balls.iter().filter(|ball| !base_ids.contains(&ball.id)).cloned().collect();
+        let removed_balls = base_ids.difference(&ball_ids).cloned().collect();
+
+        Delta {
+            base: base.ticks,
+            ticks: state.ticks,
+            players,
+            removed_players,
+            names,
+            removed_names,
+            created_balls,
+            removed_balls,
+            ejected: changed(&base.ejected, &state.ejected),
+            viruses: changed(&base.viruses, &state.viruses),
+            eaten_by: changed(&base.eaten_by, &state.eaten_by),
+            config: changed(&base.config, &state.config),
+            obstacles: changed(&base.obstacles, &state.obstacles),
+            rng: state.rng.clone(),
+            next_ball_id: state.next_ball_id,
+            accumulator: state.accumulator,
+            recent_balls_eaten: state.recent_balls_eaten,
+        }
+    }
+
+    // The state this was made from, given the one it is relative to
+    pub fn apply(self, base: &State) -> State {
+        let mut state = base.clone();
+        state.events.clear();
+
+        for id in &self.removed_players {
+            state.players.remove(id);
+        }
+        state.players.extend(self.players);
+        for id in &self.removed_names {
+            state.names.remove(id);
+        }
+        state.names.extend(self.names);
+
+        // Balls are ordered by id
+        let removed: BTreeSet<u64> = self.removed_balls.into_iter().collect();
+        state.balls.retain(|ball| !removed.contains(&ball.id));
+        state.balls.extend(self.created_balls);
+        state.balls.sort_by_key(|ball| ball.id);
+
+        if let Some(ejected) = self.ejected { state.ejected = ejected; }
+        if let Some(viruses) = self.viruses { state.viruses = viruses; }
+        if let Some(eaten_by) = self.eaten_by { state.eaten_by = eaten_by; }
+        if let Some(config) = self.config { state.config = config; }
+        if let Some(obstacles) = self.obstacles { state.obstacles = obstacles; }
+        state.rng = self.rng;
+        state.ticks = self.ticks;
+        state.next_ball_id = self.next_ball_id;
+        state.accumulator = self.accumulator;
+        state.recent_balls_eaten = self.recent_balls_eaten;
+        state
+    }
+}
+
+// The server's side, one for every client
+#[derive(Debug, Clone, Default)]
+pub struct Sender {
+    sent: VecDeque<State>, // Oldest first, starting with the newest acknowledged one
+    acked: Option<u64>,
+    since_keyframe: usize,
+}
+
+impl Sender {
+    pub fn new() -> Sender {
+        Sender::default()
+    }
+
+    // The client got the state with these ticks
+    pub fn ack(&mut self, ticks: u64) {
+        if self.sent.iter().any(|state| state.ticks == ticks) && self.acked.map(|acked| ticks > acked).unwrap_or(true) {
+            self.acked = Some(ticks);
+            while self.sent.front().map(|state| state.ticks < ticks).unwrap_or(false) {
+                self.sent.pop_front();
+            }
+        }
+    }
+
+    // A state sent earlier that hasn't been forgotten yet. The base of the last update is kept
+    // at least until the next one, so it can be encoded against it, see protocol.
+    pub fn base(&self, ticks: u64) -> Option<&State> {
+        self.sent.iter().find(|state| state.ticks == ticks)
+    }
+
+    pub fn update(&mut self, state: &State) -> Update {
+        // Whatever was sent last has arrived by the time this does
+        let world = self.sent.back()
+            .map(|last| last.config != state.config || last.obstacles != state.obstacles)
+            .unwrap_or(true);
+
+        // Nothing to send a delta to anymore, wait for an acknowledgement of a keyframe
+        if self.sent.len() == HISTORY {
+            self.sent.clear();
+            self.acked = None;
+        }
+
+        let base = match self.acked {
+            Some(acked) if self.since_keyframe < KEYFRAME_EVERY => self.sent.iter().find(|sent| sent.ticks == acked),
+            _ => None,
+        };
+
+        let update = match base {
+            Some(base) => {
+                self.since_keyframe += 1;
+                Update::Delta(Delta::between(base, state))
+            }
+            None => {
+                self.since_keyframe = 0;
+                let base = self.acked.filter(|acked| self.base(*acked).is_some());
+                Update::Keyframe(Keyframe { state: state.clone(), world, base })
+            }
+        };
+
+        let mut sent = state.clone();
+        sent.events.clear();
+        self.sent.push_back(sent);
+
+        update
+    }
+}
+
+// The client's side
+#[derive(Debug, Clone, Default)]
+pub struct Receiver {
+    received: VecDeque<State>,
+}
+
+impl Receiver {
+    pub fn new() -> Receiver {
+        Receiver::default()
+    }
+
+    // A state received earlier that hasn't been forgotten yet
+    pub fn base(&self, ticks: u64) -> Option<&State> {
+        self.received.iter().find(|state| state.ticks == ticks)
+    }
+
+    pub fn newest(&self) -> Option<&State> {
+        self.received.back()
+    }
+
+    // The whole state, whose ticks should be acknowledged. None if the delta is relative to a
+    // state we don't have, or we didn't get the config and obstacles yet.
+    pub fn receive(&mut self, update: Update) -> Option<State> {
+        let state = match update {
+            Update::Keyframe(Keyframe { state, world: true, .. }) => state,
+            Update::Keyframe(Keyframe { mut state, world: false, .. }) => {
+                let newest = self.newest()?;
+                state.config = newest.config.clone();
+                state.obstacles = newest.obstacles.clone();
+                state
+            }
+            Update::Delta(delta) => {
+                let base = self.base(delta.base)?;
+                delta.apply(base)
+            }
+        };
+
+        if self.received.len() == HISTORY {
+            self.received.pop_front();
+        }
+        self.received.push_back(state.clone());
+        Some(state)
+    }
+}
+
+#[test]
+fn test_deltas() {
+    use bots::Bots;
+
+    // A busy map, with lots of balls
+    let mut state = State::with_config(8, GameConfig { world_size: (2000., 2000.), ..GameConfig::default() });
+    state.obstacles.push(Obstacle::Circle { center: (100., 100.), radius: 20. });
+    state.fill_balls();
+    let mut bots = Bots::new();
+    bots.keep_population(&mut state, 10, |state| (1..).find(|id| !state.names.contains_key(id)).unwrap());
+
+    let mut sender = Sender::new();
+    let mut receiver = Receiver::new();
+    let (mut keyframe_bytes, mut delta_bytes, mut worlds) = (vec![], vec![], 0);
+    let mut unacked = VecDeque::new();
+    for update in 0..200 {
+        for _ in 0..6 {
+            bots.command_all(&mut state);
+            state.tick(::TICK_DT);
+        }
+        state.take_events();
+
+        let sent = sender.update(&state);
+        let bytes = ::serde_json::to_vec(&sent).unwrap().len();
+        match sent {
+            Update::Keyframe(ref keyframe) => {
+                keyframe_bytes.push(bytes);
+                if keyframe.world { worlds += 1; }
+            }
+            Update::Delta(_) => delta_bytes.push(bytes),
+        }
+        let received = receiver.receive(sent).unwrap();
+        assert_eq!(received, state);
+
+        // Acknowledgements come back two updates later, and for a while not at all
+        unacked.push_back(received.ticks);
+        if unacked.len() > 2 {
+            let ticks = unacked.pop_front().unwrap();
+            if !(100..130).contains(&update) {
+                sender.ack(ticks);
+            }
+        }
+    }
+
+    // Regular keyframes, and one after the acknowledgements stopped. Only the first has the
+    // config and obstacles.
+    assert!(keyframe_bytes.len() > 200 / KEYFRAME_EVERY);
+    assert_eq!(worlds, 1);
+    let mean = |bytes: &[usize]| bytes.iter().sum::<usize>() / bytes.len();
+    assert!(mean(&delta_bytes) * 10 < mean(&keyframe_bytes));
+}
@@ -0,0 +1,156 @@
+// Spatial indices used by State::tick to find entities close to a point.
+//
+// An index is built from a list of entities, each a position and a radius, and answers which
+// of them *might* be within some distance of a point, as indices into that list, in ascending
+// order. The distance is to the edge of the entity, so one big entity doesn't make every
+// query look far. The caller does the exact distance check, so any index returning a superset
+// of the real answer gives the same result as comparing against everything.
+//
+// In a wrapping world, "close" includes entities across the edges.
+
+pub trait SpatialIndex {
+    fn build(world_size: (f64, f64), wrap: bool, entities: &[((f64, f64), f64)]) -> Self;
+
+    // Indices of all entities that might be closer than `radius` plus their own radius to
+    // `pos`, sorted
+    fn query(&self, pos: (f64, f64), radius: f64) -> Vec<usize>;
+}
+
+// Returns every entity, which is what State::tick did before there was a grid
+pub struct BruteForce {
+    len: usize,
+}
+
+impl SpatialIndex for BruteForce {
+    fn build(_world_size: (f64, f64), _wrap: bool, entities: &[((f64, f64), f64)]) -> BruteForce {
+        BruteForce { len: entities.len() }
+    }
+
+    fn query(&self, _pos: (f64, f64), _radius: f64) -> Vec<usize> {
+        (0..self.len).collect()
+    }
+}
+
+const BUCKET_SIZE: f64 = 20.;
+
+// A uniform grid of buckets of about BUCKET_SIZE, evenly covering the world. Entities are in
+// every bucket they overlap. Those outside the world are put in the closest bucket on the
+// edge, or wrapped around if the world wraps.
+pub struct Grid {
+    width: usize,
+    height: usize,
+    bucket_size: (f64, f64),
+    wrap: bool,
+    buckets: Vec<Vec<usize>>,
+}
+
+// The bucket along one axis that x is in
+fn bucket(x: f64, bucket_size: f64, n: usize, wrap: bool) -> usize {
+    let i = (x / bucket_size).floor() as i64;
+    if wrap {
+        i.rem_euclid(n as i64) as usize
+    } else {
+        i.max(0).min(n as i64 - 1) as usize
+    }
+}
+
+// The buckets along one axis that the range lo..hi touches
+fn bucket_span(lo: f64, hi: f64, bucket_size: f64, n: usize, wrap: bool) -> Vec<usize> {
+    let (a, b) = ((lo / bucket_size).floor() as i64, (hi / bucket_size).floor() as i64);
+    if wrap && b - a + 1 >= n as i64 {
+        (0..n).collect()
+    } else if wrap {
+        (a..=b).map(|i| i.rem_euclid(n as i64) as usize).collect()
+    } else {
+        (bucket(lo, bucket_size, n, wrap)..=bucket(hi, bucket_size, n, wrap)).collect()
+    }
+}
+
+impl Grid {
+    fn buckets_around(&self, pos: (f64, f64), radius: f64) -> (Vec<usize>, Vec<usize>) {
+        (
+            bucket_span(pos.0 - radius, pos.0 + radius, self.bucket_size.0, self.width, self.wrap),
+            bucket_span(pos.1 - radius, pos.1 + radius, self.bucket_size.1, self.height, self.wrap),
+        )
+    }
+}
+
+impl SpatialIndex for Grid {
+    fn build(world_size: (f64, f64), wrap: bool, entities: &[((f64, f64), f64)]) -> Grid {
+        let width = ((world_size.0 / BUCKET_SIZE).ceil() as usize).max(1);
+        let height = ((world_size.1 / BUCKET_SIZE).ceil() as usize).max(1);
+
+        let mut grid = Grid {
+            width,
+            height,
+            bucket_size: (world_size.0 / width as f64, world_size.1 / height as f64),
+            wrap,
+            buckets: vec![vec![]; width * height],
+        };
+
+        for (i, &(pos, radius)) in entities.iter().enumerate() {
+            if radius <= 0. {
+                let x = bucket(pos.0, grid.bucket_size.0, width, wrap);
+                let y = bucket(pos.1, grid.bucket_size.1, height, wrap);
+                grid.buckets[y * width + x].push(i);
+            } else {
+                let (xs, ys) = grid.buckets_around(pos, radius);
+                for &y in &ys {
+                    for &x in &xs {
+                        grid.buckets[y * width + x].push(i);
+                    }
+                }
+            }
+        }
+
+        grid
+    }
+
+    fn query(&self, pos: (f64, f64), radius: f64) -> Vec<usize> {
+        let (xs, ys) = self.buckets_around(pos, radius);
+
+        let mut found = vec![];
+        for &y in &ys {
+            for &x in &xs {
+                found.extend(&self.buckets[y * self.width + x]);
+            }
+        }
+        // Entities with a radius can be in more than one of the buckets
+        found.sort_unstable();
+        found.dedup();
+        found
+    }
+}
+
+#[test]
+fn test_grid_finds_everything_brute_force_does() {
+    let world = (300., 200.);
+    let mut entities = vec![];
+    for i in 0..200 {
+        // Spread out a bit past the edges too, with a few big ones
+        let radius = if i % 10 == 0 { 35. } else { 0. };
+        let i = i as f64;
+        entities.push((((i * 37.) % 340. - 20., (i * 53.) % 240. - 20.), radius));
+    }
+
+    for &wrap in &[false, true] {
+        let grid = Grid::build(world, wrap, &entities);
+        let brute = BruteForce::build(world, wrap, &entities);
+
+        for &(pos, radius) in &[((0., 0.), 10.), ((150., 100.), 45.), ((310., -5.), 30.), ((70., 190.), 0.5), ((290., 10.), 25.)] {
+            let close = |i: &usize| {
+                let ((x, y), size) = entities[*i];
+                let (mut dx, mut dy) = (x - pos.0, y - pos.1);
+                if wrap {
+                    dx = (dx + world.0 / 2.).rem_euclid(world.0) - world.0 / 2.;
+                    dy = (dy + world.1 / 2.).rem_euclid(world.1) - world.1 / 2.;
+                }
+                (dx * dx + dy * dy).sqrt() < radius + size
+            };
+
+            let from_grid: Vec<usize> = grid.query(pos, radius).into_iter().filter(&close).collect();
+            let from_brute: Vec<usize> = brute.query(pos, radius).into_iter().filter(&close).collect();
+            assert_eq!(from_grid, from_brute);
+        }
+    }
+}
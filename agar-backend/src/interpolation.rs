@@ -0,0 +1,184 @@
+// Showing everyone else smoothly on the client. Updates from the server come in bursts and
+// at uneven times, so instead of showing each one as it arrives the client keeps the last few
+// and shows the game as it was `delay` seconds ago, in between two of them. Our own player
+// isn't delayed, see prediction.
+//
+// Time here is server time, State::ticks * TICK_DT. The client keeps its own clock of where
+// it is showing, which runs in real time and is nudged towards the newest update minus the
+// delay whenever one comes in. If updates are late the clock runs past the newest one and
+// players keep moving the way they were, for up to MAX_EXTRAPOLATION seconds.
+
+use std::collections::{BTreeSet, VecDeque};
+
+use {State, Player, GameConfig, TICK_DT};
+
+pub const DEFAULT_DELAY: f64 = 0.2;
+// How far past the newest update players keep moving before they stop
+const MAX_EXTRAPOLATION: f64 = 0.25;
+// How much of the way to where it should be the clock goes with every update
+const CLOCK_CORRECTION: f64 = 0.1;
+// Further off than this and the clock jumps, after a long pause for example
+const MAX_CLOCK_ERROR: f64 = 1.;
+
+#[derive(Debug, Clone)]
+pub struct Interpolation {
+    pub delay: f64,
+    snapshots: VecDeque<State>, // Oldest first
+    time: Option<f64>,
+}
+
+fn time_of(state: &State) -> f64 {
+    state.ticks as f64 * TICK_DT
+}
+
+impl Interpolation {
+    pub fn new(delay: f64) -> Interpolation {
+        Interpolation { delay, snapshots: VecDeque::new(), time: None }
+    }
+
+    // Adds an update from the server. Ones that are out of order are dropped.
+    pub fn push(&mut self, state: State) {
+        if self.snapshots.back().map(|last| last.ticks >= state.ticks).unwrap_or(false) {
+            return;
+        }
+        let target = time_of(&state) - self.delay;
+        self.snapshots.push_back(state);
+
+        self.time = Some(match self.time {
+            Some(time) if (target - time).abs() < MAX_CLOCK_ERROR => time + (target - time) * CLOCK_CORRECTION,
+            _ => target,
+        });
+        self.drop_old();
+    }
+
+    // Only the update right before the shown time is needed, and everything after
+    fn drop_old(&mut self) {
+        if let Some(time) = self.time {
+            while self.snapshots.len() > 2 && time_of(&self.snapshots[1]) <= time {
+                self.snapshots.pop_front();
+            }
+        }
+    }
+
+    // The server time being shown
+    pub fn time(&self) -> Option<f64> {
+        self.time
+    }
+
+    // Moves the clock on by dt seconds of real time
+    pub fn advance(&mut self, dt: f64) {
+        if let Some(ref mut time) = self.time {
+            *time += dt;
+        }
+        self.drop_old();
+    }
+
+    // Replaces everyone but us in state with how they are at the shown time, and the balls
+    // with the ones there were. Balls we have eaten since are left out.
+    pub fn apply(&self, state: &mut State, me: usize) {
+        let time = match self.time {
+            Some(time) => time,
+            None => return,
+        };
+
+        // The two updates around the time, or the last two when past them
+        let (a, b) = match self.snapshots.len() {
+            0 => return,
+            1 => (&self.snapshots[0], &self.snapshots[0]),
+            n => {
+                let i = (1..n).find(|i| time_of(&self.snapshots[*i]) >= time).unwrap_or(n - 1);
+                (&self.snapshots[i - 1], &self.snapshots[i])
+            }
+        };
+
+        let span = time_of(b) - time_of(a);
+        let f = if span <= 0. {
+            1.
+        } else {
+            ((time - time_of(a)) / span).max(0.).min(1. + MAX_EXTRAPOLATION / span)
+        };
+        let base = if f < 0.5 { a } else { b };
+        let other = if f < 0.5 { b } else { a };
+
+        let mine = state.players.remove(&me);
+        state.players = base.players.iter()
+            .filter(|(id, _)| **id != me)
+            .map(|(id, player)| {
+                let player = match (a.players.get(id), b.players.get(id)) {
+                    (Some(pa), Some(pb)) if other.players.contains_key(id) => lerp_player(&state.config, pa, pb, f),
+                    _ => player.clone(),
+                };
+                (*id, player)
+            })
+            .collect();
+        if let Some(mine) = mine {
+            state.players.insert(me, mine);
+        }
+
+        let current: BTreeSet<u64> = state.balls.iter().map(|ball| ball.id).collect();
+        state.balls = base.balls.iter()
+            .filter(|ball| current.contains(&ball.id))
+            .cloned()
+            .collect();
+        state.ejected = base.ejected.clone();
+        state.viruses = base.viruses.clone();
+    }
+}
+
+// Cells are matched up by index, so players who split or merged in between jump instead
+fn lerp_player(config: &GameConfig, a: &Player, b: &Player, f: f64) -> Player {
+    if a.cells.len() != b.cells.len() {
+        return if f < 0.5 { a.clone() } else { b.clone() };
+    }
+
+    let mut player = b.clone();
+    for (cell, (ca, cb)) in player.cells.iter_mut().zip(a.cells.iter().zip(b.cells.iter())) {
+        let (dx, dy) = config.delta(ca.pos, cb.pos);
+        cell.pos = config.keep_inside((ca.pos.0 + dx * f, ca.pos.1 + dy * f), 0.);
+        cell.size = ca.size + (cb.size - ca.size) * f;
+        cell.show_size = ca.show_size + (cb.show_size - ca.show_size) * f;
+    }
+    player
+}
+
+#[test]
+fn test_interpolation() {
+    use {Cell, Ball};
+
+    let at = |ticks: u64, x: f64| {
+        let mut state = State::with_seed(0);
+        state.ticks = ticks;
+        state.players.insert(1, Player { cells: vec![Cell::new((x, 100.), 10.)], direction: 0., speed: 0., color: (0, 0, 0) });
+        state.players.insert(2, Player { cells: vec![Cell::new((x, 200.), 10.)], direction: 0., speed: 0., color: (0, 0, 0) });
+        state.balls.push(Ball { id: 0, pos: (1., 1.), color: (0, 0, 0), size: 1., value: 3. });
+        state
+    };
+    let x_of = |state: &State, id: usize| state.players[&id].cells[0].pos.0;
+
+    let mut interpolation = Interpolation::new(0.0625);
+    interpolation.push(at(64, 100.));
+    assert_eq!(interpolation.time(), Some(1. - 0.0625));
+    interpolation.advance(0.125);
+    interpolation.push(at(64 + 8, 110.)); // 0.125 seconds later, right on time
+
+    // Halfway between the two. We are 1 and stay where we are.
+    let mut state = at(80, 500.);
+    interpolation.apply(&mut state, 1);
+    assert_eq!(x_of(&state, 1), 500.);
+    assert!((x_of(&state, 2) - 105.).abs() < 1e-9);
+
+    // Past the newest update, players keep going for a while and then stop
+    interpolation.advance(0.125);
+    let mut state = at(80, 500.);
+    interpolation.apply(&mut state, 1);
+    assert!((x_of(&state, 2) - 115.).abs() < 1e-9);
+    interpolation.advance(1.);
+    let mut state = at(80, 500.);
+    interpolation.apply(&mut state, 1);
+    assert!((x_of(&state, 2) - 130.).abs() < 1e-9);
+
+    // Balls we ate are gone right away
+    state.balls.clear();
+    interpolation.apply(&mut state, 1);
+    assert!(state.balls.is_empty());
+}
@@ -3,6 +3,9 @@ extern crate serde_derive;
 
 extern crate serde;
 
+#[cfg(test)]
+extern crate serde_json;
+
 #[cfg(feature="server-side")]
 extern crate rand;
 
@@ -10,119 +13,675 @@ extern crate rand;
 mod math;
 use math::*;
 
-use std::collections::{HashMap, HashSet};
-use std::mem;
+pub mod config;
+pub use config::{GameConfig, BallKind, WorldMode};
 
-#[cfg(feature = "server-side")]
-const BALL_PROB_PER_SEC: f64 = 0.4;
+pub mod names;
 
-const GROW_SPEED: f64 = 4.;
-const SIZE_RATIO_TO_EAT: f64 = 1.2;
+pub mod chat;
 
-#[derive(Serialize, Deserialize, Debug)]
+pub mod bots;
+
+pub mod replay;
+
+pub mod snapshot;
+
+pub mod prediction;
+
+pub mod interpolation;
+
+pub mod delta;
+
+pub mod view;
+
+pub mod protocol;
+
+pub mod rng;
+pub use rng::Rng;
+
+pub mod obstacle;
+pub use obstacle::{Obstacle, Arena};
+use obstacle::push_out_of_all;
+
+pub mod grid;
+use grid::{SpatialIndex, Grid};
+#[cfg(test)]
+use grid::BruteForce;
+
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::cmp::Ordering;
+
+// The simulation always moves in steps of TICK_DT seconds, see State::advance
+pub const TICK_DT: f64 = 1. / 64.;
+const MAX_STEPS_PER_ADVANCE: usize = 32;
+
+// Events nobody takes are dropped, oldest first, after this many
+const MAX_EVENTS: usize = 4096;
+
+// Attempts at finding a spot outside obstacles before spawning something anyway
+const MAX_SPAWN_TRIES: usize = 16;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct State {
-    pub players: HashMap<usize, Player>,
-    pub size: (f64, f64),
+    // Ordered maps so that ticks don't depend on hashing, see State::with_seed
+    pub players: BTreeMap<usize, Player>,
+    // The names players joined with. Kept while they are out of the game, so they respawn
+    // with the same name.
+    pub names: BTreeMap<usize, String>,
+    pub config: GameConfig,
     pub balls: Vec<Ball>,
-    // Every time x is eaten by y, (x: y) is added. This is used by the clients to
-    // keep track of whom to follow with the camera
-    pub eaten_by: HashMap<usize, usize>,
+    pub ejected: Vec<EjectedMass>,
+    pub viruses: Vec<Virus>,
+    // Never change during a game, so clients get them once, see delta::Keyframe
+    pub obstacles: Vec<Obstacle>,
+    // When x is eaten by y, (x: y) is added. The entry is removed when x respawns or
+    // leaves, so this only holds players who are currently out of the game.
+    pub eaten_by: BTreeMap<usize, usize>,
+    // Everything spawned is placed and colored by this
+    pub rng: Rng,
+    // Given to the next ball spawned or mass ejected, so both are ordered by id
+    pub next_ball_id: u64,
+    // Number of ticks simulated so far
+    pub ticks: u64,
+    // Time passed to advance that hasn't been simulated yet
+    pub accumulator: f64,
+    // Balls eaten recently, decaying over config.ball_eaten_window
+    pub recent_balls_eaten: f64,
+    // Things that happened since the last take_events, not sent along with the state
+    #[serde(skip)]
+    pub events: VecDeque<GameEvent>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum GameEvent {
+    PlayerEaten { victim: usize, eater: usize },
+    BallEaten { eater: usize },
+    PlayerSpawned { id: usize },
+    PlayerLeft { id: usize },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Ball {
+    pub id: u64, // Unique in a game, see delta
     pub pos: (f64, f64),
     pub color: (u8, u8, u8),
+    pub size: f64,
+    pub value: f64, // In size^2
 }
 
+impl Ball {
+    // A ball of a random kind somewhere in the world, None if there are no kinds
+    fn random(rng: &mut Rng, config: &GameConfig, obstacles: &[Obstacle], id: u64) -> Option<Ball> {
+        let total: f64 = config.ball_kinds.iter().map(|kind| kind.weight).sum();
+        if total <= 0. {
+            return None;
+        }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct Player {
+        let mut pick = rng.gen_range(0., total);
+        let mut kind = &config.ball_kinds[0];
+        for k in &config.ball_kinds {
+            kind = k;
+            if pick < k.weight { break }
+            pick -= k.weight;
+        }
+
+        Some(Ball {
+            id,
+            pos: random_free_pos(rng, config.world_size, obstacles, kind.size),
+            color: rng.gen_color(),
+            size: kind.size,
+            value: kind.value,
+        })
+    }
+}
+
+
+// Mass shot out by a player, which slides to a halt and can be eaten by any cell
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EjectedMass {
+    pub id: u64, // From State::next_ball_id
+    pub pos: (f64, f64),
+    pub vel: (f64, f64),
+    pub size: f64,
+    pub color: (u8, u8, u8),
+}
+
+// Smaller cells can hide under a virus, bigger cells touching it get popped into pieces.
+// Feeding a virus ejected mass makes it grow until it shoots off a new virus.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Virus {
     pub pos: (f64, f64),
+    pub vel: (f64, f64),
+    pub size: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Player {
+    pub cells: Vec<Cell>,
     pub direction: f64, // Radians
     pub speed: f64,
+    pub color: (u8, u8, u8),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Cell {
+    pub pos: (f64, f64),
     pub size: f64,
     pub show_size: f64,
-    pub color: (u8, u8, u8),
+    pub vel: (f64, f64), // Velocity from being launched by a split, decays over time
+    pub merge_timer: f64, // Seconds left until the cell can merge with its siblings
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct IdPlayerCommand {
     pub id: usize,
     pub command: PlayerCommand
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum PlayerCommand {
-    SetDirectionAndSpeed(f64, f64)
+    SetDirectionAndSpeed(f64, f64),
+    Split,
+    EjectMass,
+    // Comes back into the game after being eaten
+    Respawn,
+}
+
+// Everything a client sends to the server
+#[derive(Serialize, Deserialize, Debug)]
+pub enum ClientMessage {
+    // Sent once after connecting, to get a player
+    Join(String),
+    // Sent right after connecting with the session token of an earlier connection, to get
+    // its player back
+    Resume(String),
+    // Numbered from 1, see prediction::Prediction
+    Command(u64, IdPlayerCommand),
+    // Sent for every update with the ticks of its state, so the next ones can be deltas
+    Ack(u64),
+    Chat(String),
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum SynchrosizationMessage {
     Command(IdPlayerCommand),
-    SyncState(State)
+    SyncState(Box<State>)
+}
+
+impl Player {
+    // The radius of a single cell with the same area as all cells combined
+    pub fn size(&self) -> f64 {
+        self.mass().sqrt()
+    }
+
+    // Total area of all cells, in size^2
+    pub fn mass(&self) -> f64 {
+        self.cells.iter().map(|cell| cell.size * cell.size).sum()
+    }
+
+    // Area-weighted center of all cells. Measured from the first cell, so that cells on
+    // both sides of an edge in a wrapping world are still close to the center.
+    pub fn center(&self, config: &GameConfig) -> (f64, f64) {
+        let origin = match self.cells.first() {
+            Some(cell) => cell.pos,
+            None => return (0., 0.),
+        };
+
+        let mut total = 0.;
+        let mut offset = (0., 0.);
+        for cell in &self.cells {
+            let area = cell.size * cell.size;
+            let (dx, dy) = config.delta(origin, cell.pos);
+            offset.0 += dx * area;
+            offset.1 += dy * area;
+            total += area;
+        }
+        if total == 0. {
+            return origin;
+        }
+        config.keep_inside((origin.0 + offset.0 / total, origin.1 + offset.1 / total), 0.)
+    }
+
+    fn split(&mut self, config: &GameConfig) {
+        let (dir_x, dir_y) = (sin(self.direction), cos(self.direction));
+
+        let n_cells = self.cells.len();
+        let mut new_cells = vec![];
+        for cell in &mut self.cells {
+            if n_cells + new_cells.len() >= config.max_cells {
+                break;
+            }
+            if cell.size < config.min_split_size {
+                continue;
+            }
+
+            // Halve the area
+            cell.size /= 2f64.sqrt();
+            cell.merge_timer = config.merge_time;
+
+            new_cells.push(Cell {
+                pos: (cell.pos.0 + dir_x * cell.size, cell.pos.1 + dir_y * cell.size),
+                size: cell.size,
+                show_size: cell.size,
+                vel: (dir_x * config.split_speed, dir_y * config.split_speed),
+                merge_timer: config.merge_time,
+            });
+        }
+        self.cells.extend(new_cells);
+    }
+
+    fn eject_mass(&mut self, config: &GameConfig, next_id: &mut u64) -> Vec<EjectedMass> {
+        let (dir_x, dir_y) = (sin(self.direction), cos(self.direction));
+        let size = config.eject_area.sqrt();
+
+        let mut ejected = vec![];
+        for cell in &mut self.cells {
+            // Cells that would have nothing left, with a config where min_eject_size is too
+            // small for eject_area_loss
+            if cell.size < config.min_eject_size || cell.size * cell.size <= config.eject_area_loss {
+                continue;
+            }
+
+            cell.size = (cell.size * cell.size - config.eject_area_loss).sqrt();
+
+            let dist = cell.size + size;
+            ejected.push(EjectedMass {
+                id: *next_id,
+                pos: (cell.pos.0 + dir_x * dist, cell.pos.1 + dir_y * dist),
+                vel: (dir_x * config.eject_speed, dir_y * config.eject_speed),
+                size,
+                color: self.color,
+            });
+            *next_id += 1;
+        }
+        ejected
+    }
+
+    // Splits cell i into virus_pop_pieces equally sized pieces flying out in all directions,
+    // or as many as max_cells allows
+    fn pop(&mut self, i: usize, config: &GameConfig) {
+        let n_pieces = config.virus_pop_pieces.min(config.max_cells + 1 - self.cells.len().min(config.max_cells));
+        if n_pieces < 2 {
+            return;
+        }
+
+        let size = self.cells[i].size / (n_pieces as f64).sqrt();
+        let pos = self.cells[i].pos;
+
+        self.cells[i].size = size;
+        self.cells[i].merge_timer = config.merge_time;
+
+        for piece in 1..n_pieces {
+            let angle = piece as f64 / n_pieces as f64 * 2. * ::std::f64::consts::PI;
+            let (dir_x, dir_y) = (sin(angle), cos(angle));
+            self.cells.push(Cell {
+                pos: (pos.0 + dir_x * size, pos.1 + dir_y * size),
+                size,
+                show_size: size,
+                vel: (dir_x * config.split_speed, dir_y * config.split_speed),
+                merge_timer: config.merge_time,
+            });
+        }
+    }
+}
+
+// A random position at least margin inside the world, and at least margin away from every
+// obstacle unless no such spot was found
+fn random_free_pos(rng: &mut Rng, world: (f64, f64), obstacles: &[Obstacle], margin: f64) -> (f64, f64) {
+    let mut pos = (0., 0.);
+    for _ in 0..MAX_SPAWN_TRIES {
+        pos = ( rng.gen_range(margin, world.0 - margin), rng.gen_range(margin, world.1 - margin) );
+        if obstacles.iter().all(|obstacle| obstacle.push_out(pos, margin).is_none()) {
+            break;
+        }
+    }
+    pos
+}
+
+// Something a cell eats by covering it, in State::tick
+trait Pellet {
+    fn pos(&self) -> (f64, f64);
+    // How far inside the cell the pellet has to be to get eaten
+    fn margin(&self) -> f64;
+    // Size of a cell of the given size after eating the pellet
+    fn grow(&self, size: f64) -> f64;
+}
+
+impl Pellet for Ball {
+    fn pos(&self) -> (f64, f64) { self.pos }
+    fn margin(&self) -> f64 { self.size }
+    fn grow(&self, size: f64) -> f64 { ((size + 0.1).powi(2) + self.value).sqrt() }
+}
+
+impl Pellet for EjectedMass {
+    fn pos(&self) -> (f64, f64) { self.pos }
+    fn margin(&self) -> f64 { self.size }
+    fn grow(&self, size: f64) -> f64 { (size * size + self.size * self.size).sqrt() }
+}
+
+// Lets the cell eat every pellet it covers that isn't gone yet, in order, growing as it goes
+// Returns how many were eaten
+fn eat_pellets<P: Pellet, I: SpatialIndex>(cell: &mut Cell, pellets: &[P], gone: &mut [bool], index: &I, config: &GameConfig) -> usize {
+    // The cell grows while eating, so the pellets have to be looked up within its final size
+    let mut radius = cell.size;
+    loop {
+        let mut size = cell.size;
+        let mut eaten = vec![];
+        for i in index.query(cell.pos, radius) {
+            if gone[i] { continue }
+            let pellet = &pellets[i];
+
+            let (dx, dy) = config.delta(cell.pos, pellet.pos());
+            let dist = (dx * dx + dy * dy).sqrt();
+            if dist < size - pellet.margin() {
+                size = pellet.grow(size);
+                eaten.push(i);
+            }
+        }
+
+        if size <= radius {
+            cell.size = size;
+            for &i in &eaten {
+                gone[i] = true;
+            }
+            return eaten.len();
+        }
+        radius = size * 1.5;
+    }
+}
+
+fn remove_marked<T>(items: &mut Vec<T>, marked: &[bool]) {
+    let mut i = 0;
+    items.retain(|_| {
+        i += 1;
+        !marked[i - 1]
+    });
+}
+
+impl Virus {
+    pub fn new(pos: (f64, f64), size: f64) -> Virus {
+        Virus {
+            pos,
+            vel: (0., 0.),
+            size,
+        }
+    }
+}
+
+impl Cell {
+    pub fn new(pos: (f64, f64), size: f64) -> Cell {
+        Cell {
+            pos,
+            size,
+            show_size: 0.,
+            vel: (0., 0.),
+            merge_timer: 0.,
+        }
+    }
+}
+
+// For games that don't have to be played again the same way
+#[cfg(feature = "server-side")]
+pub fn random_seed() -> u64 {
+    use rand::{thread_rng, Rng};
+    thread_rng().gen::<u64>()
+}
+
+impl Default for State {
+    fn default() -> State {
+        State::new()
+    }
 }
 
 impl State {
+    // On the server the seed is random, on the client it doesn't matter as the state is
+    // replaced by the server's
     pub fn new() -> State {
+        #[cfg(feature = "server-side")]
+        let seed = random_seed();
+        #[cfg(not(feature = "server-side"))]
+        let seed = 0;
+
+        State::with_seed(seed)
+    }
+
+    // Given the same seed, and the same commands at the same ticks, two states stay identical
+    pub fn with_seed(seed: u64) -> State {
+        State::with_config(seed, GameConfig::default())
+    }
+
+    pub fn with_config(seed: u64, config: GameConfig) -> State {
         State {
-            players: HashMap::new(),
+            players: BTreeMap::new(),
+            names: BTreeMap::new(),
+            config,
             balls: vec![],
-            size: (1000., 1000.),
-            eaten_by: HashMap::new(),
+            ejected: vec![],
+            viruses: vec![],
+            obstacles: vec![],
+            eaten_by: BTreeMap::new(),
+            rng: Rng::new(seed),
+            next_ball_id: 0,
+            ticks: 0,
+            accumulator: 0.,
+            recent_balls_eaten: 0.,
+            events: VecDeque::new(),
+        }
+    }
+
+    fn push_event(&mut self, event: GameEvent) {
+        if self.events.len() == MAX_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    // All events since the last call, oldest first
+    pub fn take_events(&mut self) -> Vec<GameEvent> {
+        self.events.drain(..).collect()
+    }
+
+    // Simulates `elapsed` seconds in fixed steps of TICK_DT, so that the result doesn't depend
+    // on how often this is called. Time not adding up to a whole step is kept for the next
+    // call. If too much time has passed, the rest is skipped rather than trying to catch up.
+    // Returns the number of steps taken.
+    pub fn advance(&mut self, elapsed: f64) -> usize {
+        self.accumulator += elapsed;
+
+        let mut steps = 0;
+        while self.accumulator >= TICK_DT {
+            if steps == MAX_STEPS_PER_ADVANCE {
+                self.accumulator = 0.;
+                break;
+            }
+            self.tick(TICK_DT);
+            self.accumulator -= TICK_DT;
+            steps += 1;
         }
+        steps
     }
 
     pub fn tick(&mut self, dt: f64) {
-        for (_id, player) in self.players.iter_mut() {
+        self.tick_with::<Grid>(dt);
+        self.ticks += 1;
 
-            player.show_size = (player.show_size - player.size) * (1. / GROW_SPEED).powf(dt) + player.size;
+        #[cfg(feature = "server-side")]
+        self.do_server_side_stuff(dt);
+    }
 
-            let speed = player.speed / (player.size + 5.);
+    // The whole tick, using I to find which entities are close enough to interact
+    fn tick_with<I: SpatialIndex>(&mut self, dt: f64) {
+        let wrap = self.config.world_mode == WorldMode::Wrapped;
 
-            let (dx, dy) = (speed * sin(player.direction), speed * cos(player.direction));
-            player.pos.0 += dx * dt * 35.;
-            player.pos.1 += dy * dt * 35.;
+        for ejected in &mut self.ejected {
+            ejected.pos.0 += ejected.vel.0 * dt;
+            ejected.pos.1 += ejected.vel.1 * dt;
 
-            if player.pos.0 < player.show_size {
-                player.pos.0 = player.show_size;
-            }
-            if player.pos.1 < player.show_size {
-                player.pos.1 = player.show_size;
-            }
-            if player.pos.0 > self.size.0 - player.show_size {
-                player.pos.0 = self.size.0 - player.show_size;
+            let friction = (1. / self.config.eject_friction).powf(dt);
+            ejected.vel.0 *= friction;
+            ejected.vel.1 *= friction;
+
+            ejected.pos = self.config.keep_inside(ejected.pos, ejected.size);
+            ejected.pos = push_out_of_all(&self.obstacles, ejected.pos, ejected.size);
+        }
+
+        for virus in &mut self.viruses {
+            virus.pos.0 += virus.vel.0 * dt;
+            virus.pos.1 += virus.vel.1 * dt;
+
+            let friction = (1. / self.config.virus_friction).powf(dt);
+            virus.vel.0 *= friction;
+            virus.vel.1 *= friction;
+
+            virus.pos = self.config.keep_inside(virus.pos, virus.size);
+            virus.pos = push_out_of_all(&self.obstacles, virus.pos, virus.size);
+        }
+
+        // Feed viruses ejected mass, shooting off a new virus in the direction it was fed
+        let ejected_index = I::build(self.config.world_size, wrap, &self.ejected.iter().map(|e| (e.pos, 0.)).collect::<Vec<_>>());
+        let mut ejected_gone = vec![false; self.ejected.len()];
+        let mut new_viruses = vec![];
+        for virus in &mut self.viruses {
+            // A virus never gets bigger than virus_max_size before eating
+            for i in ejected_index.query(virus.pos, virus.size.max(self.config.virus_max_size)) {
+                if ejected_gone[i] { continue }
+                let ejected = &self.ejected[i];
+
+                let (dx, dy) = self.config.delta(virus.pos, ejected.pos);
+                let dist = (dx * dx + dy * dy).sqrt();
+                if dist < virus.size {
+                    ejected_gone[i] = true;
+                    virus.size = (virus.size * virus.size + ejected.size * ejected.size).sqrt();
+
+                    if virus.size >= self.config.virus_max_size {
+                        virus.size = self.config.virus_size;
+
+                        let speed = (ejected.vel.0 * ejected.vel.0 + ejected.vel.1 * ejected.vel.1).sqrt();
+                        let (dir_x, dir_y) =
+                            if speed > 0. { (ejected.vel.0 / speed, ejected.vel.1 / speed) }
+                            else if dist > 0. { (-dx / dist, -dy / dist) }
+                            else { (0., 1.) };
+
+                        let mut shot = Virus::new(virus.pos, self.config.virus_size);
+                        shot.vel = (dir_x * self.config.virus_speed, dir_y * self.config.virus_speed);
+                        new_viruses.push(shot);
+                    }
+                }
             }
-            if player.pos.1 > self.size.1 - player.show_size {
-                player.pos.1 = self.size.1 - player.show_size;
+        }
+        self.viruses.extend(new_viruses);
+
+        let ball_index = I::build(self.config.world_size, wrap, &self.balls.iter().map(|b| (b.pos, 0.)).collect::<Vec<_>>());
+        let mut balls_gone = vec![false; self.balls.len()];
+        let virus_index = I::build(self.config.world_size, wrap, &self.viruses.iter().map(|v| (v.pos, v.size)).collect::<Vec<_>>());
+        let mut viruses_gone = vec![false; self.viruses.len()];
+
+        let mut balls_eaten = vec![];
+        for (id, player) in self.players.iter_mut() {
+            let (dir_x, dir_y) = (sin(player.direction), cos(player.direction));
+
+            for cell in &mut player.cells {
+                cell.show_size = (cell.show_size - cell.size) * (1. / self.config.grow_speed).powf(dt) + cell.size;
+                cell.merge_timer = (cell.merge_timer - dt).max(0.);
+
+                let speed = player.speed / (cell.size + 5.);
+
+                cell.pos.0 += dir_x * speed * dt * self.config.speed_factor + cell.vel.0 * dt;
+                cell.pos.1 += dir_y * speed * dt * self.config.speed_factor + cell.vel.1 * dt;
+
+                let friction = (1. / self.config.split_friction).powf(dt);
+                cell.vel.0 *= friction;
+                cell.vel.1 *= friction;
+
+                cell.pos = self.config.keep_inside(cell.pos, cell.show_size);
+                cell.pos = push_out_of_all(&self.obstacles, cell.pos, cell.show_size);
+
+                for _ in 0..eat_pellets(cell, &self.balls, &mut balls_gone, &ball_index, &self.config) {
+                    balls_eaten.push(*id);
+                }
+                eat_pellets(cell, &self.ejected, &mut ejected_gone, &ejected_index, &self.config);
             }
 
-            self.balls.retain(|ball| {
-                        let (dx, dy) = (ball.pos.0 - player.pos.0, ball.pos.1 - player.pos.1);
+            // Cells bigger than a virus eat it as soon as they touch it, and get popped.
+            // Smaller cells can hide under it.
+            for i in 0..player.cells.len() {
+                let mut popped = false;
+                {
+                    let cell = &mut player.cells[i];
+                    for j in virus_index.query(cell.pos, cell.size) {
+                        let virus = &self.viruses[j];
+                        if viruses_gone[j] || cell.size <= virus.size { continue }
+
+                        let (dx, dy) = self.config.delta(cell.pos, virus.pos);
                         let dist = (dx * dx + dy * dy).sqrt();
-                        if dist < player.size - 1. {
-                            player.size = ((player.size + 0.1).powi(2) + 3.).sqrt();
-                            false
-                        } else {
-                            true
+                        if dist < cell.size + virus.size {
+                            cell.size = (cell.size * cell.size + virus.size * virus.size).sqrt();
+                            viruses_gone[j] = true;
+                            popped = true;
+                            break;
                         }
-                    });
+                    }
+                }
+                if popped {
+                    player.pop(i, &self.config);
+                }
+            }
         }
 
-        let mut old_players = mem::replace(&mut self.players, HashMap::new());
+        self.recent_balls_eaten += balls_eaten.len() as f64;
+        for eater in balls_eaten {
+            self.push_event(GameEvent::BallEaten { eater });
+        }
+
+        remove_marked(&mut self.balls, &balls_gone);
+        remove_marked(&mut self.ejected, &ejected_gone);
+        remove_marked(&mut self.viruses, &viruses_gone);
 
-        // Suck in other players
-        let mut succ: HashMap<usize, (f64, (f64, f64))> = HashMap::new(); // Id: (amount, to)
+        // Take every cell out of its player, tagged with the owner's id. The cells of each
+        // player end up next to each other, in the ranges in `owned`.
+        let mut cells: Vec<(usize, Cell)> = vec![];
+        let mut owned: Vec<(usize, usize)> = vec![];
+        for (id, player) in self.players.iter_mut() {
+            let start = cells.len();
+            cells.extend(player.cells.drain(..).map(|cell| (*id, cell)));
+            owned.push((start, cells.len()));
+        }
 
-        for (id, player) in &old_players {
-            for (oid, other) in &old_players {
+        // Cells of the same player push each other apart until they are allowed to merge
+        for &(start, end) in &owned {
+            for i in start..end {
+                for j in i + 1..end {
+                    if cells[i].1.merge_timer <= 0. && cells[j].1.merge_timer <= 0. { continue }
+
+                    let (a, b) = (&cells[i].1, &cells[j].1);
+                    let (dx, dy) = self.config.delta(a.pos, b.pos);
+                    let dist = (dx * dx + dy * dy).sqrt();
+                    let overlap = a.size + b.size - dist;
+                    if overlap > 0. && dist > 0. {
+                        let (push_x, push_y) = (dx / dist * overlap / 2., dy / dist * overlap / 2.);
+                        cells[i].1.pos.0 -= push_x;
+                        cells[i].1.pos.1 -= push_y;
+                        cells[j].1.pos.0 += push_x;
+                        cells[j].1.pos.1 += push_y;
+                    }
+                }
+            }
+        }
+
+        // Suck in other players' cells
+        let cell_index = I::build(self.config.world_size, wrap, &cells.iter().map(|(_, cell)| (cell.pos, cell.size)).collect::<Vec<_>>());
+        let mut succ: HashMap<usize, (f64, (f64, f64))> = HashMap::new(); // Cell index: (amount, to)
+
+        for (id, cell) in &cells {
+            for j in cell_index.query(cell.pos, cell.size) {
+                let (oid, other) = (&cells[j].0, &cells[j].1);
                 if oid == id { continue }
 
-                if other.size < player.size / SIZE_RATIO_TO_EAT {
-                    let (dx, dy) = (other.pos.0 - player.pos.0, other.pos.1 - player.pos.1);
+                if other.size < cell.size / self.config.size_ratio_to_eat {
+                    let (dx, dy) = self.config.delta(cell.pos, other.pos);
                     let dist = (dx * dx + dy * dy).sqrt();
-                    if dist < player.size + other.size {
-                        // ps = player.size, os = other.size
+                    if dist < cell.size + other.size {
+                        // ps = cell.size, os = other.size
                         // ms = ps - os (Min succ, the lowest possible distance to not get eaten)
                         // Ms = ps + os (Max succ, the highest distance for the succ to have effect)
                         //
@@ -137,115 +696,702 @@ impl State {
                         // f(d) = d / (ms-Ms) - Ms/(ms-Ms)
                         //      = d / (-2os) + ps / (2os) + 1/2
 
-                        let succ_amount = dist / (-2. * other.size) + player.size / (2. * other.size) + 0.5;
+                        let succ_amount = dist / (-2. * other.size) + cell.size / (2. * other.size) + 0.5;
 
-                        succ.insert(*oid, (succ_amount, player.pos));
+                        succ.insert(j, (succ_amount, cell.pos));
                     }
                 }
             }
         }
 
-        for (id, (amount, to)) in succ {
-            if let Some(mut player) = old_players.get_mut(&id) {
-                let (dx, dy) = (to.0 - player.pos.0, to.1 - player.pos.1);
-                player.pos.0 += dx * dt * amount * 3.;
-                player.pos.1 += dy * dt * amount * 3.;
-            }
+        for (j, (amount, to)) in succ {
+            let cell = &mut cells[j].1;
+            let (dx, dy) = self.config.delta(cell.pos, to);
+            cell.pos.0 += dx * dt * amount * 3.;
+            cell.pos.1 += dy * dt * amount * 3.;
         }
 
 
-        // Eat players
-        let mut eaten_ids = HashSet::new();
-        let mut size_adds: HashMap<usize, f64> = HashMap::new();
-        for (id, player) in &old_players {
+        // Merge own cells whose timers have run out into the bigger one
+        let mut merged = HashSet::new();
+        for &(start, end) in &owned {
+            for i in start..end {
+                for j in start..end {
+                    if i == j { continue }
+                    if merged.contains(&i) || merged.contains(&j) { continue }
 
-            for (oid, other) in &old_players {
-                if oid == id { continue }
-                if other.size < player.size / SIZE_RATIO_TO_EAT {
-                    let (dx, dy) = (other.pos.0 - player.pos.0, other.pos.1 - player.pos.1);
+                    let (cell, other) = (&cells[i].1, &cells[j].1);
+                    if cell.merge_timer > 0. || other.merge_timer > 0. { continue }
+                    if other.size > cell.size || (other.size == cell.size && j < i) { continue }
+
+                    let (dx, dy) = self.config.delta(cell.pos, other.pos);
                     let dist = (dx * dx + dy * dy).sqrt();
-                    if dist < player.size - other.size {
-                        eaten_ids.insert(*oid);
+                    if dist < cell.size {
+                        let size = (cell.size * cell.size + other.size * other.size).sqrt();
+                        cells[i].1.size = size;
+                        merged.insert(j);
+                    }
+                }
+            }
+        }
 
-                        self.eaten_by.insert(*oid, *id);
+        // Eat other players' cells
+        let cell_index = I::build(self.config.world_size, wrap, &cells.iter().map(|(_, cell)| (cell.pos, 0.)).collect::<Vec<_>>());
+        let mut eaten = HashSet::new();
+        let mut area_adds: HashMap<usize, f64> = HashMap::new();
+        let mut eaten_players: BTreeMap<usize, usize> = BTreeMap::new(); // Victim id: eater id
+        for (i, (id, cell)) in cells.iter().enumerate() {
+            if merged.contains(&i) { continue }
 
-                        let old_size = size_adds.get(id).unwrap_or(&0.).clone();
+            for j in cell_index.query(cell.pos, cell.size) {
+                let (oid, other) = (&cells[j].0, &cells[j].1);
+                if oid == id || merged.contains(&j) { continue }
 
-                        size_adds.insert(*id, old_size + other.size);
+                if other.size < cell.size / self.config.size_ratio_to_eat {
+                    let (dx, dy) = self.config.delta(cell.pos, other.pos);
+                    let dist = (dx * dx + dy * dy).sqrt();
+                    if dist < cell.size - other.size {
+                        eaten.insert(j);
+                        eaten_players.insert(*oid, *id);
+
+                        *area_adds.entry(i).or_insert(0.) += other.size * other.size;
                     }
                 }
             }
         }
 
-        for (id, mut player) in old_players {
+        for (i, (id, mut cell)) in cells.into_iter().enumerate() {
+            if merged.contains(&i) || eaten.contains(&i) { continue }
+
             // Area addition:
-            //     r1 = player radius, r2 = other radius, r3 = resulting radius
+            //     r1 = cell radius, r2 = other radius, r3 = resulting radius
             // a1 = pi * r1 ^ 2
             // a2 = pi * r2 ^ 2
             // a3 = a1 + a2 = pi * (r1 ^ 2 + r2 ^ 2)
             // a3 = pi * r3 ^ 2
             // r3 ^ 2 = r1 ^ 2 + r2 ^ 2
             // r3 = sqrt(r1 ^ 2 + r2 ^ 2)
-            player.size = (player.size * player.size + size_adds.get(&id).map(|s| s * s).unwrap_or(0.)).sqrt();
+            cell.size = (cell.size * cell.size + area_adds.get(&i).cloned().unwrap_or(0.)).sqrt();
+            // Pushing and sucking can move cells over an edge
+            if self.config.world_mode == WorldMode::Wrapped {
+                cell.pos = self.config.keep_inside(cell.pos, 0.);
+            }
 
-            if !eaten_ids.contains(&id) {
-                self.players.insert(id, player);
+            if let Some(player) = self.players.get_mut(&id) {
+                player.cells.push(cell);
             }
         }
 
-        #[cfg(feature = "server-side")]
-        self.do_server_side_stuff(dt);
+        // A player is only eaten once all of its cells are gone
+        for (id, eater) in eaten_players {
+            if self.players.get(&id).map(|player| player.cells.is_empty()).unwrap_or(false) {
+                self.players.remove(&id);
+                self.eaten_by.insert(id, eater);
+                self.push_event(GameEvent::PlayerEaten { victim: id, eater });
+            }
+        }
+    }
+
+    // How many balls spawning aims for
+    pub fn target_balls(&self) -> usize {
+        let area = self.config.world_size.0 * self.config.world_size.1;
+        ((area * self.config.ball_density) as usize).min(self.config.max_balls)
     }
 
-    #[cfg(feature = "server-side")]
+    // Spawns balls until there are target_balls, for a new world
+    pub fn fill_balls(&mut self) {
+        let target = self.target_balls();
+        while self.balls.len() < target {
+            match Ball::random(&mut self.rng, &self.config, &self.obstacles, self.next_ball_id) {
+                Some(ball) => {
+                    self.balls.push(ball);
+                    self.next_ball_id += 1;
+                }
+                None => break,
+            }
+        }
+    }
+
+    // Spawns balls and viruses. Only called by tick on the server, but available everywhere
     pub fn do_server_side_stuff(&mut self, dt: f64) {
-        use rand::{thread_rng, Rng};
-        let mut rng = thread_rng();
-
-        // We want rand() < x repeated 1/dt times be true with probability BALL_PROB_PER_SEC.
-        // The probability of rand() < x is x, so
-        // 1-(1-x)^(1/dt) = BALL_PROB_PER_SEC
-        // (1-x)^(1/dt) = 1 - BALL_PROB_PER_SEC
-        // 1-x = (1 - BALL_PROB_PER_SEC)^dt
-        // x = 1 - (1 - BALL_PROB_PER_SEC) ^ dt
-        if rng.gen::<f64>() < 1. - (1. - BALL_PROB_PER_SEC).powf(dt) {
-            // Add ball
-            self.balls.push(
-                Ball {
-                    pos: ( rng.gen_range(1., self.size.0 - 1.), rng.gen_range(1., self.size.1 - 1.) ),
-                    color: rng.gen::<(u8, u8, u8)>()
-                });
-        }
-    }
-
-    #[cfg(feature = "server-side")]
-    pub fn add_player(&mut self, id: usize) {
-        use rand::{thread_rng, Rng};
+        let target = self.target_balls();
+        let rng = &mut self.rng;
+        let config = &self.config;
+
+        self.recent_balls_eaten *= (-dt / config.ball_eaten_window).exp();
+
+        if self.balls.len() < target {
+            // On average rate * dt balls this tick
+            let rate = config.ball_spawn_per_sec + self.recent_balls_eaten / config.ball_eaten_window;
+            let expected = rate * dt;
+            let mut n = expected as usize;
+            if rng.gen_f64() < expected - n as f64 {
+                n += 1;
+            }
+
+            for _ in 0..n.min(target - self.balls.len()) {
+                if let Some(ball) = Ball::random(rng, config, &self.obstacles, self.next_ball_id) {
+                    self.balls.push(ball);
+                    self.next_ball_id += 1;
+                }
+            }
+        }
 
-        let mut rng = thread_rng();
+        if self.viruses.len() < config.virus_count {
+            let size = config.virus_size;
+            self.viruses.push(
+                Virus::new(random_free_pos(rng, config.world_size, &self.obstacles, size), size)
+            );
+        }
+    }
+
+    pub fn add_player(&mut self, id: usize) {
+        let rng = &mut self.rng;
+        let size = self.config.start_size;
 
         let player = Player {
-            pos: ( rng.gen_range(0., self.size.0), rng.gen_range(0., self.size.1) ),
+            cells: vec![Cell::new(random_free_pos(rng, self.config.world_size, &self.obstacles, size), size)],
             direction: 0.,
             speed: 0.,
-            size: 3.,
-            show_size: 0.,
-            color: rng.gen::<(u8, u8, u8)>()
+            color: rng.gen_color()
         };
 
         self.players.insert(id, player);
+        self.push_event(GameEvent::PlayerSpawned { id });
+    }
+
+    // The n biggest players as (id, mass), biggest first
+    pub fn leaderboard(&self, n: usize) -> Vec<(usize, f64)> {
+        let mut ranking: Vec<(usize, f64)> = self.players.iter().map(|(id, player)| (*id, player.mass())).collect();
+        ranking.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal).then(a.0.cmp(&b.0)));
+        ranking.truncate(n);
+        ranking
+    }
 
+    // Adds a player with a name, which should have been checked with names::validate_name
+    pub fn join(&mut self, id: usize, name: String) {
+        self.names.insert(id, name);
+        self.add_player(id);
+    }
+
+    // Removes a player who left the game, along with everything pointing the camera at it
+    pub fn remove_player(&mut self, id: usize) -> Option<Player> {
+        self.eaten_by.retain(|victim, eater| *victim != id && *eater != id);
+        let named = self.names.remove(&id).is_some();
+        let player = self.players.remove(&id);
+        if named || player.is_some() {
+            self.push_event(GameEvent::PlayerLeft { id });
+        }
+        player
     }
 
     pub fn do_command(&mut self, command: IdPlayerCommand) {
+        if let PlayerCommand::Respawn = command.command {
+            // Only players who are out of the game can come back, and only if they were in it.
+            // Everyone else has to join, with a name.
+            let was_in = self.eaten_by.contains_key(&command.id) || self.names.contains_key(&command.id);
+            if was_in && !self.players.contains_key(&command.id) {
+                self.eaten_by.remove(&command.id);
+                self.add_player(command.id);
+            }
+            return;
+        }
+
         if let Some(player) = self.players.get_mut(&command.id) {
             match command.command {
                 PlayerCommand::SetDirectionAndSpeed(dir, speed) => {
                     player.direction = dir;
                     player.speed = speed.max(1.);
                 }
+                PlayerCommand::Split => {
+                    player.split(&self.config);
+                }
+                PlayerCommand::EjectMass => {
+                    self.ejected.extend(player.eject_mass(&self.config, &mut self.next_ball_id));
+                }
+                PlayerCommand::Respawn => {}
+            }
+        }
+    }
+}
+
+
+#[test]
+fn test_split() {
+    let mut state = State::new();
+    // Nothing spawned on the server side gets in the way
+    state.config.virus_count = 0;
+    state.config.ball_density = 0.;
+    state.players.insert(1, Player {
+        cells: vec![Cell::new((500., 500.), 8.)],
+        direction: 0.,
+        speed: 0.,
+        color: (0, 0, 0),
+    });
+
+    state.do_command(IdPlayerCommand { id: 1, command: PlayerCommand::Split });
+
+    let player = &state.players[&1];
+    assert_eq!(player.cells.len(), 2);
+    assert!((player.size() - 8.).abs() < 1e-9);
+    assert!(player.cells[1].pos.1 > player.cells[0].pos.1);
+
+    // Too small to split any further
+    state.do_command(IdPlayerCommand { id: 1, command: PlayerCommand::Split });
+    assert_eq!(state.players[&1].cells.len(), 2);
+}
+
+#[test]
+fn test_eject_mass() {
+    let mut state = State::new();
+    // Nothing spawned on the server side gets in the way
+    state.config.virus_count = 0;
+    state.config.ball_density = 0.;
+    state.players.insert(1, Player {
+        cells: vec![Cell::new((500., 500.), 10.)],
+        direction: 0.,
+        speed: 0.,
+        color: (0, 0, 0),
+    });
+
+    state.do_command(IdPlayerCommand { id: 1, command: PlayerCommand::EjectMass });
+    let config = state.config.clone();
+
+    assert_eq!(state.ejected.len(), 1);
+    assert!((state.players[&1].size().powi(2) - (100. - config.eject_area_loss)).abs() < 1e-9);
+
+    // The pellet slides away and stops
+    let start = state.ejected[0].pos;
+    for _ in 0..50 {
+        state.tick(0.1);
+    }
+    let end = state.ejected[0].pos;
+    assert!(end.1 > start.1 + 10.);
+    assert!(state.ejected[0].vel.1.abs() < 1e-3);
+
+    // Walking back over it eats it
+    state.players.get_mut(&1).unwrap().cells[0].pos = end;
+    state.tick(0.01);
+    assert!(state.ejected.is_empty());
+    assert!((state.players[&1].size().powi(2) - (100. - config.eject_area_loss + config.eject_area)).abs() < 1e-9);
+
+    // Cells can't eject more than they have
+    state.config.min_eject_size = 0.;
+    state.config.eject_area_loss = 1000.;
+    state.do_command(IdPlayerCommand { id: 1, command: PlayerCommand::EjectMass });
+    assert!(state.ejected.is_empty());
+    assert!(!state.players[&1].size().is_nan());
+}
+
+#[test]
+fn test_virus_pops_bigger_cells() {
+    let mut state = State::new();
+    state.config.virus_count = 0;
+    let config = state.config.clone();
+    state.viruses.push(Virus::new((500., 500.), config.virus_size));
+    state.players.insert(1, Player {
+        cells: vec![Cell::new((500., 500.), config.virus_size / 2.)],
+        direction: 0.,
+        speed: 0.,
+        color: (0, 0, 0),
+    });
+
+    // Small cells hide under the virus
+    state.tick(0.01);
+    assert_eq!(state.viruses.len(), 1);
+    assert_eq!(state.players[&1].cells.len(), 1);
+
+    // Anything bigger pops as soon as it touches it
+    let size = config.virus_size * 1.1;
+    state.players.get_mut(&1).unwrap().cells[0] = Cell::new((500. + config.virus_size + size - 1., 500.), size);
+    state.tick(0.01);
+    assert!(state.viruses.is_empty());
+    assert_eq!(state.players[&1].cells.len(), config.virus_pop_pieces);
+    let expected = (size * size + config.virus_size * config.virus_size).sqrt();
+    assert!((state.players[&1].size() - expected).abs() < 1e-9);
+}
+
+#[test]
+fn test_virus_feeding() {
+    let mut state = State::new();
+    state.config.virus_count = 0;
+    let config = state.config.clone();
+    state.viruses.push(Virus::new((500., 500.), config.virus_size));
+
+    let mut fed = 0;
+    while state.viruses.len() == 1 {
+        state.ejected.push(EjectedMass {
+            id: fed,
+            pos: (500., 490.),
+            vel: (0., config.eject_speed),
+            size: config.eject_area.sqrt(),
+            color: (0, 0, 0),
+        });
+        state.tick(0.1);
+        fed += 1;
+        assert!(fed < 100);
+    }
+
+    assert_eq!(state.viruses.len(), 2);
+    assert_eq!(state.viruses[0].size, config.virus_size);
+    assert!(state.viruses[1].vel.1 > 0.);
+}
+
+#[test]
+fn test_split_cells_merge_back() {
+    let mut state = State::new();
+    // Nothing spawned on the server side gets in the way
+    state.config.virus_count = 0;
+    state.config.ball_density = 0.;
+    state.players.insert(1, Player {
+        cells: vec![Cell::new((500., 500.), 10.)],
+        direction: 0.,
+        speed: 0.,
+        color: (0, 0, 0),
+    });
+    state.do_command(IdPlayerCommand { id: 1, command: PlayerCommand::Split });
+    let config = state.config.clone();
+
+    for _ in 0..(config.merge_time * 10.) as usize + 100 {
+        state.tick(0.1);
+        if state.players[&1].cells.len() == 1 {
+            break;
+        }
+        // Pull the cells together once they may merge
+        if state.players[&1].cells[0].merge_timer <= 0. {
+            let pos = state.players[&1].cells[0].pos;
+            for cell in state.players.get_mut(&1).unwrap().cells.iter_mut() {
+                cell.pos = pos;
             }
         }
     }
+
+    let player = &state.players[&1];
+    assert_eq!(player.cells.len(), 1);
+    assert!((player.size() - 10.).abs() < 1e-9);
+}
+
+#[test]
+fn test_grid_tick_matches_brute_force() {
+    grid_tick_matches_brute_force(WorldMode::Walled);
+}
+
+#[test]
+fn test_wrapped_grid_tick_matches_brute_force() {
+    grid_tick_matches_brute_force(WorldMode::Wrapped);
+}
+
+#[cfg(test)]
+fn grid_tick_matches_brute_force(world_mode: WorldMode) {
+    // xorshift, to not need rand on the client side
+    let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut rand = move || {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        (seed >> 11) as f64 / (1u64 << 53) as f64
+    };
+
+    let mut state = State::new();
+    state.config.world_size = (300., 300.);
+    state.config.world_mode = world_mode;
+    for id in 0..60 {
+        let mut cells = vec![];
+        for _ in 0..1 + (rand() * 4.) as usize {
+            let mut cell = Cell::new((rand() * 300., rand() * 300.), 2. + rand() * 12.);
+            cell.merge_timer = if rand() < 0.5 { 0. } else { rand() * 2. };
+            cells.push(cell);
+        }
+        state.players.insert(id, Player {
+            cells,
+            direction: rand() * 2. * ::std::f64::consts::PI,
+            speed: rand() * 8.,
+            color: (0, 0, 0),
+        });
+    }
+    for id in 0..2000 {
+        let size = if rand() < 0.5 { 1. } else { 2. };
+        state.balls.push(Ball { id, pos: (rand() * 300., rand() * 300.), color: (0, 0, 0), size, value: size * size * 3. });
+    }
+    for id in 0..100 {
+        state.ejected.push(EjectedMass {
+            id,
+            pos: (rand() * 300., rand() * 300.),
+            vel: (rand() * 40. - 20., rand() * 40. - 20.),
+            size: state.config.eject_area.sqrt(),
+            color: (0, 0, 0),
+        });
+    }
+    for _ in 0..10 {
+        let size = state.config.virus_size;
+        state.viruses.push(Virus::new((rand() * 300., rand() * 300.), size));
+    }
+
+    let mut brute = state.clone();
+    for _ in 0..200 {
+        state.tick_with::<Grid>(0.05);
+        brute.tick_with::<BruteForce>(0.05);
+        assert_eq!(state, brute);
+    }
+
+    // Make sure something actually happened
+    assert!(state.balls.len() < 2000);
+    assert!(!state.eaten_by.is_empty());
+}
+
+#[test]
+fn test_same_seed_same_game() {
+    let run = |seed| {
+        let mut state = State::with_seed(seed);
+        for id in 0..20 {
+            state.add_player(id);
+        }
+        for tick in 0..500 {
+            let id = tick % 20;
+            let dir = tick as f64 * 0.37;
+            state.do_command(IdPlayerCommand { id, command: PlayerCommand::SetDirectionAndSpeed(dir, 6.) });
+            if tick % 50 == 0 {
+                state.do_command(IdPlayerCommand { id, command: PlayerCommand::EjectMass });
+            }
+
+            state.tick(0.075);
+            #[cfg(not(feature = "server-side"))]
+            state.do_server_side_stuff(0.075);
+        }
+        state
+    };
+
+    let a = run(7);
+    assert_eq!(a, run(7));
+    assert!(!a.balls.is_empty());
+    assert_ne!(a, run(8));
+}
+
+#[test]
+fn test_advance_fixed_steps() {
+    let mut state = State::with_seed(0);
+
+    assert_eq!(state.advance(TICK_DT * 2.5), 2);
+    assert_eq!(state.advance(TICK_DT * 0.25), 0);
+    assert_eq!(state.advance(TICK_DT * 0.25), 1);
+    assert_eq!(state.ticks, 3);
+    assert_eq!(state.accumulator, 0.);
+
+    // Far too much time, like after the tab was in the background
+    assert_eq!(state.advance(100.), MAX_STEPS_PER_ADVANCE);
+    assert_eq!(state.ticks, 3 + MAX_STEPS_PER_ADVANCE as u64);
+    assert_eq!(state.accumulator, 0.);
+}
+
+#[test]
+fn test_advance_frame_rate_independent() {
+    let run = |frame: f64| {
+        let mut state = State::with_seed(3);
+        for id in 0..10 {
+            state.add_player(id);
+            state.do_command(IdPlayerCommand { id, command: PlayerCommand::SetDirectionAndSpeed(id as f64, 5.) });
+        }
+        for _ in 0..(4. / frame) as usize {
+            state.advance(frame);
+        }
+        state.accumulator = 0.;
+        state
+    };
+
+    // 1/16 and 1/32 both add up to whole steps
+    let a = run(1. / 16.);
+    assert_eq!(a.ticks, 256);
+    assert_eq!(a, run(1. / 32.));
+}
+
+#[test]
+fn test_remove_player() {
+    let mut state = State::with_seed(0);
+    state.join(1, "one".to_string());
+    state.add_player(2);
+    state.eaten_by.insert(3, 1);
+    state.eaten_by.insert(4, 2);
+
+    assert!(state.remove_player(1).is_some());
+    assert!(state.remove_player(1).is_none());
+    assert_eq!(state.take_events().last(), Some(&GameEvent::PlayerLeft { id: 1 }));
+    // Only players that were there leave
+    state.remove_player(5);
+    assert!(state.take_events().is_empty());
+    assert!(!state.players.contains_key(&1));
+    assert!(!state.names.contains_key(&1));
+    assert!(state.players.contains_key(&2));
+    assert_eq!(state.eaten_by.get(&3), None);
+    assert_eq!(state.eaten_by.get(&4), Some(&2));
+}
+
+#[test]
+fn test_respawn_after_being_eaten() {
+    let mut state = State::with_seed(0);
+    state.config.virus_count = 0;
+    state.players.insert(1, Player {
+        cells: vec![Cell::new((500., 500.), 20.)],
+        direction: 0.,
+        speed: 0.,
+        color: (0, 0, 0),
+    });
+    state.players.insert(2, Player {
+        cells: vec![Cell::new((505., 500.), 5.)],
+        direction: 0.,
+        speed: 0.,
+        color: (0, 0, 0),
+    });
+
+    // Can't respawn while still alive
+    state.do_command(IdPlayerCommand { id: 2, command: PlayerCommand::Respawn });
+    assert_eq!(state.players[&2].cells[0].size, 5.);
+
+    state.tick(0.01);
+    assert!(!state.players.contains_key(&2));
+    assert_eq!(state.eaten_by.get(&2), Some(&1));
+
+    state.do_command(IdPlayerCommand { id: 2, command: PlayerCommand::Respawn });
+    assert!(state.players.contains_key(&2));
+    assert_eq!(state.players[&2].size(), state.config.start_size);
+    assert_eq!(state.eaten_by.get(&2), None);
+
+    let events = state.take_events();
+    assert!(events.contains(&GameEvent::PlayerEaten { victim: 2, eater: 1 }));
+    assert_eq!(events.last(), Some(&GameEvent::PlayerSpawned { id: 2 }));
+    assert!(state.take_events().is_empty());
+
+    // Players who never joined can't get a cell by respawning
+    state.do_command(IdPlayerCommand { id: 3, command: PlayerCommand::Respawn });
+    assert!(!state.players.contains_key(&3));
+    assert!(state.take_events().is_empty());
+}
+
+#[test]
+fn test_respawn_is_only_for_eaten_players() {
+    let mut state = State::with_seed(0);
+    state.join(1, "alive".to_string());
+    state.take_events();
+
+    // Not for players that are alive
+    let alive = state.players[&1].clone();
+    state.do_command(IdPlayerCommand { id: 1, command: PlayerCommand::Respawn });
+    assert_eq!(state.players[&1], alive);
+
+    // Nor for ids that never joined
+    state.do_command(IdPlayerCommand { id: 2, command: PlayerCommand::Respawn });
+    assert!(!state.players.contains_key(&2));
+    assert!(state.take_events().is_empty());
+}
+
+#[test]
+fn test_events_are_bounded() {
+    let mut state = State::with_seed(0);
+    for id in 0..MAX_EVENTS + 10 {
+        state.add_player(id);
+        state.remove_player(id);
+    }
+
+    let events = state.take_events();
+    assert_eq!(events.len(), MAX_EVENTS);
+    assert_eq!(events.last(), Some(&GameEvent::PlayerLeft { id: MAX_EVENTS + 9 }));
+    assert!(state.eaten_by.is_empty());
+}
+
+#[test]
+fn test_leaderboard() {
+    let mut state = State::with_seed(0);
+    for (id, sizes) in [(1, vec![3.]), (2, vec![4., 4.]), (3, vec![5.]), (4, vec![1.])] {
+        state.players.insert(id, Player {
+            cells: sizes.into_iter().map(|size| Cell::new((0., 0.), size)).collect(),
+            direction: 0.,
+            speed: 0.,
+            color: (0, 0, 0),
+        });
+    }
+
+    assert_eq!(state.leaderboard(3), vec![(2, 32.), (3, 25.), (1, 9.)]);
+    assert_eq!(state.leaderboard(10).len(), 4);
+}
+
+#[test]
+fn test_balls_spawn_up_to_target() {
+    let mut state = State::with_seed(3);
+    state.config.world_size = (500., 400.);
+    state.config.ball_density = 0.001;
+    assert_eq!(state.target_balls(), 200);
+
+    state.fill_balls();
+    assert_eq!(state.balls.len(), 200);
+
+    // No more than the target, even when spawning fast
+    state.config.ball_spawn_per_sec = 1000.;
+    state.do_server_side_stuff(1.);
+    assert_eq!(state.balls.len(), 200);
+
+    // max_balls caps the density
+    state.config.max_balls = 50;
+    assert_eq!(state.target_balls(), 50);
+    let mut capped = State::with_config(3, state.config.clone());
+    capped.fill_balls();
+    assert_eq!(capped.balls.len(), 50);
+}
+
+#[test]
+fn test_eaten_balls_spawn_faster() {
+    let spawned = |recent_balls_eaten| {
+        let mut state = State::with_seed(5);
+        state.recent_balls_eaten = recent_balls_eaten;
+        for _ in 0..64 {
+            state.do_server_side_stuff(TICK_DT);
+        }
+        state.balls.len()
+    };
+
+    assert!(spawned(0.) <= 3);
+    assert!(spawned(100.) >= 5);
+}
+
+#[test]
+fn test_cells_slide_along_obstacles() {
+    let mut state = State::with_seed(0);
+    state.config.virus_count = 0;
+    state.obstacles.push(Obstacle::Rect { min: (100., 100.), max: (200., 200.) });
+
+    // Heading down and to the right, into the top of the rect
+    let mut cell = Cell::new((120., 90.), 5.);
+    cell.vel = (30., 30.);
+    state.players.insert(1, Player { cells: vec![cell], direction: 0., speed: 0., color: (0, 0, 0) });
+
+    for _ in 0..20 {
+        state.tick(0.05);
+        let cell = &state.players[&1].cells[0];
+        assert!(cell.pos.1 <= 100. - cell.show_size + 1e-9);
+    }
+
+    // Stopped by the rect going down, but kept going right
+    let cell = &state.players[&1].cells[0];
+    assert!(cell.pos.0 > 130.);
 }
 
+#[test]
+fn test_wrapped_world() {
+    let mut state = State::with_seed(0);
+    state.config.world_mode = WorldMode::Wrapped;
+    state.config.virus_count = 0;
+    let world = state.config.world_size;
+
+    // Moving out on the right brings the cell in on the left
+    let mut cell = Cell::new((world.0 - 1., 500.), 5.);
+    cell.vel = (40., 0.);
+    state.players.insert(1, Player { cells: vec![cell], direction: 0., speed: 0., color: (0, 0, 0) });
+    state.tick(0.1);
+    let pos = state.players[&1].cells[0].pos;
+    assert!(pos.0 >= 0. && pos.0 < 5.);
+
+    // A cell can eat across the edge
+    state.players.insert(1, Player { cells: vec![Cell::new((1., 200.), 20.)], direction: 0., speed: 0., color: (0, 0, 0) });
+    state.players.insert(2, Player { cells: vec![Cell::new((world.0 - 2., 200.), 3.)], direction: 0., speed: 0., color: (0, 0, 0) });
+    state.tick(0.01);
+    assert!(!state.players.contains_key(&2));
+    assert_eq!(state.eaten_by.get(&2), Some(&1));
+}
@@ -0,0 +1,57 @@
+// Checking the names players join with. Used by the server, and by the client to tell the
+// player what's wrong before joining.
+
+use std::fmt;
+
+pub const MAX_NAME_LENGTH: usize = 16;
+
+#[derive(Debug, PartialEq)]
+pub enum NameError {
+    TooLong,
+    ControlCharacter,
+    Denied,
+}
+
+impl fmt::Display for NameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NameError::TooLong => write!(f, "Names can be at most {} characters long", MAX_NAME_LENGTH),
+            NameError::ControlCharacter => write!(f, "Names can't contain control characters"),
+            NameError::Denied => write!(f, "That name isn't allowed"),
+        }
+    }
+}
+
+// Returns the name with surrounding whitespace removed. An empty name is fine, the player
+// just won't have a name drawn. Any name containing a word from the deny list (ignoring
+// case) is denied.
+pub fn validate_name(name: &str, deny_list: &[String]) -> Result<String, NameError> {
+    let name = name.trim();
+
+    if name.chars().count() > MAX_NAME_LENGTH {
+        return Err(NameError::TooLong);
+    }
+    if name.chars().any(|ch| ch.is_control()) {
+        return Err(NameError::ControlCharacter);
+    }
+
+    let lower = name.to_lowercase();
+    if deny_list.iter().any(|word| !word.is_empty() && lower.contains(&word.to_lowercase())) {
+        return Err(NameError::Denied);
+    }
+
+    Ok(name.to_string())
+}
+
+#[test]
+fn test_validate_name() {
+    let deny_list = vec!["badword".to_string()];
+
+    assert_eq!(validate_name("  loovjo ", &deny_list), Ok("loovjo".to_string()));
+    assert_eq!(validate_name("", &deny_list), Ok("".to_string()));
+    assert_eq!(validate_name("åäö blobb", &deny_list), Ok("åäö blobb".to_string()));
+
+    assert_eq!(validate_name("a very long name indeed", &deny_list), Err(NameError::TooLong));
+    assert_eq!(validate_name("tab\tname", &deny_list), Err(NameError::ControlCharacter));
+    assert_eq!(validate_name("xXBadWordXx", &deny_list), Err(NameError::Denied));
+}
@@ -0,0 +1,155 @@
+// Static geometry in the world. Cells, ejected mass and viruses can't overlap obstacles; they
+// get pushed out along the shortest way, so anything moving into one slides along its edge.
+//
+// In the files the server loads, every obstacle is a table with a `shape`:
+//
+//     [[obstacles]]
+//     shape = "Rect"
+//     min = [100.0, 100.0]
+//     max = [200.0, 150.0]
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "shape")]
+pub enum Obstacle {
+    Circle { center: (f64, f64), radius: f64 },
+    // Axis aligned
+    Rect { min: (f64, f64), max: (f64, f64) },
+    // Convex, with the points in either winding order
+    Polygon { points: Vec<(f64, f64)> },
+}
+
+// The obstacles of a world, as loaded from a file
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Arena {
+    #[serde(default)]
+    pub obstacles: Vec<Obstacle>,
+}
+
+impl Obstacle {
+    // Where a circle at pos has to be moved to not overlap the obstacle, None if it doesn't
+    pub fn push_out(&self, pos: (f64, f64), radius: f64) -> Option<(f64, f64)> {
+        match self {
+            Obstacle::Circle { center, radius: own_radius } => {
+                let (dx, dy) = (pos.0 - center.0, pos.1 - center.1);
+                let dist = (dx * dx + dy * dy).sqrt();
+                let min_dist = own_radius + radius;
+                if dist >= min_dist {
+                    None
+                } else if dist == 0. {
+                    Some((center.0 + min_dist, center.1))
+                } else {
+                    Some((center.0 + dx / dist * min_dist, center.1 + dy / dist * min_dist))
+                }
+            }
+            Obstacle::Rect { min, max } => {
+                let corners = [*min, (max.0, min.1), *max, (min.0, max.1)];
+                push_out_of_polygon(&corners, pos, radius)
+            }
+            Obstacle::Polygon { points } => push_out_of_polygon(points, pos, radius),
+        }
+    }
+}
+
+// Pushes pos out of every obstacle in turn
+pub fn push_out_of_all(obstacles: &[Obstacle], pos: (f64, f64), radius: f64) -> (f64, f64) {
+    let mut pos = pos;
+    for obstacle in obstacles {
+        if let Some(new_pos) = obstacle.push_out(pos, radius) {
+            pos = new_pos;
+        }
+    }
+    pos
+}
+
+fn push_out_of_polygon(points: &[(f64, f64)], pos: (f64, f64), radius: f64) -> Option<(f64, f64)> {
+    if points.len() < 3 {
+        return None;
+    }
+
+    // Positive for counter clockwise points, which makes (e.1, -e.0) point outwards for
+    // every edge e
+    let area: f64 = points.iter().zip(points.iter().cycle().skip(1))
+        .map(|(a, b)| a.0 * b.1 - b.0 * a.1)
+        .sum();
+    let winding = if area < 0. { -1. } else { 1. };
+
+    // The edge pos is furthest outside of, and the closest point on the outline
+    let mut most_outside = (f64::NEG_INFINITY, (0., 0.));
+    let mut closest = (f64::INFINITY, (0., 0.));
+    for (a, b) in points.iter().zip(points.iter().cycle().skip(1)) {
+        let e = (b.0 - a.0, b.1 - a.1);
+        let len = (e.0 * e.0 + e.1 * e.1).sqrt();
+        if len == 0. { continue }
+
+        let normal = (winding * e.1 / len, -winding * e.0 / len);
+        let outside = (pos.0 - a.0) * normal.0 + (pos.1 - a.1) * normal.1;
+        if outside > most_outside.0 {
+            most_outside = (outside, normal);
+        }
+
+        let t = (((pos.0 - a.0) * e.0 + (pos.1 - a.1) * e.1) / (len * len)).clamp(0., 1.);
+        let on_edge = (a.0 + e.0 * t, a.1 + e.1 * t);
+        let (dx, dy) = (pos.0 - on_edge.0, pos.1 - on_edge.1);
+        let dist = (dx * dx + dy * dy).sqrt();
+        if dist < closest.0 {
+            closest = (dist, on_edge);
+        }
+    }
+
+    let (outside, normal) = most_outside;
+    if outside <= 0. {
+        // Inside, out through the closest edge
+        let push = radius - outside;
+        return Some((pos.0 + normal.0 * push, pos.1 + normal.1 * push));
+    }
+
+    let (dist, on_edge) = closest;
+    if dist >= radius {
+        None
+    } else {
+        Some((on_edge.0 + (pos.0 - on_edge.0) / dist * radius, on_edge.1 + (pos.1 - on_edge.1) / dist * radius))
+    }
+}
+
+#[cfg(test)]
+fn assert_close(a: (f64, f64), b: (f64, f64)) {
+    assert!((a.0 - b.0).abs() < 1e-9 && (a.1 - b.1).abs() < 1e-9, "{:?} != {:?}", a, b);
+}
+
+#[test]
+fn test_push_out() {
+    let circle = Obstacle::Circle { center: (0., 0.), radius: 10. };
+    assert_eq!(circle.push_out((20., 0.), 5.), None);
+    assert_close(circle.push_out((0., 12.), 5.).unwrap(), (0., 15.));
+
+    let rect = Obstacle::Rect { min: (0., 0.), max: (10., 20.) };
+    assert_eq!(rect.push_out((15., 5.), 4.), None);
+    // From the side, a corner, and from inside through the closest edge
+    assert_close(rect.push_out((12., 5.), 4.).unwrap(), (14., 5.));
+    assert_eq!(rect.push_out((13., 24.), 5.), None);
+    let diagonal = 5. / 2f64.sqrt();
+    assert_close(rect.push_out((13., 23.), 5.).unwrap(), (10. + diagonal, 20. + diagonal));
+    assert_close(rect.push_out((2., 10.), 1.).unwrap(), (-1., 10.));
+
+    // The same triangle in both windings
+    let ccw = Obstacle::Polygon { points: vec![(0., 0.), (10., 0.), (0., 10.)] };
+    let cw = Obstacle::Polygon { points: vec![(0., 0.), (0., 10.), (10., 0.)] };
+    for triangle in &[ccw, cw] {
+        assert_eq!(triangle.push_out((10., 10.), 1.), None);
+        assert_close(triangle.push_out((5., -0.5), 1.).unwrap(), (5., -1.));
+        assert_close(triangle.push_out((1., 3.), 1.).unwrap(), (-1., 3.));
+    }
+}
+
+#[test]
+fn test_arena_file() {
+    let arena: Arena = ::serde_json::from_str(r#"{ "obstacles": [
+        { "shape": "Circle", "center": [1, 2], "radius": 3 },
+        { "shape": "Polygon", "points": [[0, 0], [1, 0], [0, 1]] }
+    ] }"#).unwrap();
+
+    assert_eq!(arena.obstacles, vec![
+        Obstacle::Circle { center: (1., 2.), radius: 3. },
+        Obstacle::Polygon { points: vec![(0., 0.), (1., 0.), (0., 1.)] },
+    ]);
+}
@@ -0,0 +1,130 @@
+// Client side prediction. The client applies its own commands right away instead of waiting
+// for the server, and numbers them. Every update from the server says up to which number it
+// has applied them; the client takes the server's state and applies the commands the server
+// hasn't seen yet on top of it again, ticking in between like it did the first time. That way
+// our own cells end up where we predicted them instead of snapping back to where the server
+// had them an instant ago.
+
+use std::collections::VecDeque;
+
+use {State, IdPlayerCommand, PlayerCommand};
+
+// More is skipped rather than simulated, when updates stop coming for a while
+const MAX_REPLAY_TICKS: u64 = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+struct Pending {
+    seq: u64,
+    tick: u64, // State::ticks when the command was applied
+    command: IdPlayerCommand,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Prediction {
+    next_seq: u64,
+    pending: VecDeque<Pending>,
+}
+
+impl Prediction {
+    pub fn new() -> Prediction {
+        Prediction::default()
+    }
+
+    // Applies a command locally, returning the number to send it to the server with.
+    // Respawns aren't predicted, we would end up somewhere else than on the server.
+    pub fn command(&mut self, state: &mut State, command: IdPlayerCommand) -> u64 {
+        self.next_seq += 1;
+        if command.command != PlayerCommand::Respawn {
+            state.do_command(command.clone());
+            self.pending.push_back(Pending { seq: self.next_seq, tick: state.ticks, command });
+        }
+        self.next_seq
+    }
+
+    // Commands the server hasn't acknowledged yet
+    pub fn unacknowledged(&self) -> usize {
+        self.pending.len()
+    }
+
+    // Replaces our state with the server's, which has applied our commands up to and
+    // including acked, and redoes the rest on top of it
+    pub fn reconcile(&mut self, state: &mut State, server: State, acked: u64) {
+        while self.pending.front().map(|pending| pending.seq <= acked).unwrap_or(false) {
+            self.pending.pop_front();
+        }
+
+        let accumulator = state.accumulator;
+        let now = state.ticks;
+        let mut predicted = server;
+
+        if let Some(first) = self.pending.front().map(|pending| pending.tick) {
+            // The server's state is taken to be from when the oldest unacknowledged command
+            // was sent, which is about right as it hasn't arrived yet
+            let start = first.max(now.saturating_sub(MAX_REPLAY_TICKS));
+            let offset = predicted.ticks as i64 - start as i64;
+
+            for pending in self.pending.iter_mut() {
+                let tick = pending.tick.max(start);
+                while predicted.ticks < (tick as i64 + offset) as u64 {
+                    predicted.tick(::TICK_DT);
+                }
+                predicted.do_command(pending.command.clone());
+                pending.tick = (tick as i64 + offset) as u64;
+            }
+            while predicted.ticks < (now as i64 + offset) as u64 {
+                predicted.tick(::TICK_DT);
+            }
+        }
+
+        // The time between ticks is ours, not the server's
+        predicted.accumulator = accumulator;
+        *state = predicted;
+    }
+}
+
+#[test]
+fn test_reconcile() {
+    use GameConfig;
+
+    let command = |dir: f64| IdPlayerCommand { id: 1, command: PlayerCommand::SetDirectionAndSpeed(dir, 6.) };
+
+    let mut server = State::with_config(2, GameConfig { virus_count: 0, ball_density: 0., ..GameConfig::default() });
+    server.join(1, "me".to_string());
+    let mut client = server.clone();
+    let mut prediction = Prediction::new();
+
+    // The client moves right, then down. The server only got the first command by the time
+    // it sends an update, three ticks after the second was sent.
+    let first = prediction.command(&mut client, command(0.));
+    for _ in 0..5 {
+        client.tick(::TICK_DT);
+    }
+    prediction.command(&mut client, command(1.5));
+    for _ in 0..3 {
+        client.tick(::TICK_DT);
+    }
+
+    server.do_command(command(0.));
+    for _ in 0..5 {
+        server.tick(::TICK_DT);
+    }
+
+    let predicted = client.clone();
+    prediction.reconcile(&mut client, server.clone(), first);
+    assert_eq!(prediction.unacknowledged(), 1);
+    assert_eq!(client.players[&1], predicted.players[&1]);
+    assert_eq!(client.ticks, server.ticks + 3);
+
+    // Once everything is acknowledged the server's state is taken as it is
+    server.do_command(command(1.5));
+    prediction.reconcile(&mut client, server.clone(), first + 1);
+    assert_eq!(prediction.unacknowledged(), 0);
+    assert_eq!(client, server);
+
+    // Respawns wait for the server
+    server.players.remove(&1);
+    let mut dead = server.clone();
+    prediction.command(&mut dead, IdPlayerCommand { id: 1, command: PlayerCommand::Respawn });
+    assert_eq!(prediction.unacknowledged(), 0);
+    assert!(!dead.players.contains_key(&1));
+}
@@ -0,0 +1,976 @@
+// The messages between the server and its clients, encoded by hand to be small:
+//
+// - Ids, counts and ticks are varints, seven bits to a byte with the high bit set on all but
+//   the last one. Ids in order are sent as the difference to the one before.
+// - Positions of cells, ejected mass and viruses are u32 fractions of the world size, those of
+//   balls u16, as balls are small and never move.
+// - Sizes, speeds and the like are f32. The config, obstacles and commands stay f64 so both
+//   sides simulate with the same numbers.
+// - Colours of players, balls and ejected mass are left out when the client has them with the
+//   same colour in the update's base: the state a delta is relative to, or the newest state the
+//   client acknowledged for a keyframe. So they are sent once while the client sees them, and
+//   again only when they come back into view or a player respawns with a new colour.
+// - The rng isn't sent, clients mustn't know what spawns next. See view::visible.
+//
+// Everything is little endian. Positions and sizes come back slightly off, so a decoded state is
+// close to the one encoded rather than equal to it.
+
+use std::fmt;
+use std::collections::BTreeMap;
+
+use {State, Player, Cell, Ball, EjectedMass, Virus, GameEvent, GameConfig, BallKind, WorldMode,
+     Obstacle, Rng, ClientMessage, IdPlayerCommand, PlayerCommand};
+use chat::ChatMessage;
+use delta::{Update, Keyframe, Delta, Receiver};
+
+// Everything the server sends a client with every update
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerMessage {
+    pub update: Update,
+    pub id: usize, // The client's player
+    pub acked: u64, // The last of the client's commands that was applied, see prediction
+    pub events: Vec<GameEvent>,
+    pub leaderboard: Vec<(usize, f64)>,
+    pub chat: Vec<ChatMessage>,
+    pub token: Option<String>, // The session token, when it changed
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+    UnexpectedEnd,
+    TrailingBytes,
+    BadVarint,
+    BadTag(u8),
+    BadString,
+    // An update relative to a state the client doesn't have
+    UnknownBase(u64),
+    // An update without the colour of something its base doesn't have
+    MissingColor(u64),
+    // A keyframe without the config and obstacles, before we got them
+    MissingWorld,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEnd => write!(f, "The message ended too early"),
+            DecodeError::TrailingBytes => write!(f, "The message goes on after its end"),
+            DecodeError::BadVarint => write!(f, "A varint is too long"),
+            DecodeError::BadTag(tag) => write!(f, "Unknown tag {}", tag),
+            DecodeError::BadString => write!(f, "A string isn't UTF-8"),
+            DecodeError::UnknownBase(ticks) => write!(f, "An update is relative to state {}, which we don't have", ticks),
+            DecodeError::MissingColor(id) => write!(f, "No colour for {}, which the base doesn't have", id),
+            DecodeError::MissingWorld => write!(f, "No config and obstacles to go with a keyframe"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EncodeError {
+    // A delta without the state it is relative to, see delta::Sender::base
+    MissingBase(u64),
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EncodeError::MissingBase(ticks) => write!(f, "A delta is relative to state {}, which wasn't given", ticks),
+        }
+    }
+}
+
+const KEYFRAME: u8 = 0;
+const DELTA: u8 = 1;
+
+// Which of the delta's optional parts are there
+const HAS_EJECTED: u8 = 1;
+const HAS_VIRUSES: u8 = 2;
+const HAS_EATEN_BY: u8 = 4;
+const HAS_CONFIG: u8 = 8;
+const HAS_OBSTACLES: u8 = 16;
+
+// base is the state the update is relative to, see Update::base
+pub fn encode_server_message(message: &ServerMessage, base: Option<&State>) -> Result<Vec<u8>, EncodeError> {
+    let mut w = Writer::default();
+
+    match message.update {
+        Update::Keyframe(Keyframe { ref state, world: has_world, base: base_ticks }) => {
+            w.u8(KEYFRAME);
+            w.varint(state.ticks);
+            w.u8(has_world as u8);
+            if has_world {
+                w.config(&state.config);
+                w.obstacles(&state.obstacles);
+            }
+            // Without the state itself, colours are all sent
+            let base_ticks = base_ticks.filter(|_| base.is_some());
+            match base_ticks {
+                Some(ticks) => { w.u8(1); w.varint(ticks); }
+                None => w.u8(0),
+            }
+            let base = base_ticks.and(base);
+            let world = state.config.world_size;
+            w.players(world, &state.players, base);
+            w.names(&state.names);
+            w.balls(world, &state.balls, base);
+            w.ejected(world, &state.ejected, base);
+            w.viruses(world, &state.viruses);
+            w.eaten_by(&state.eaten_by);
+            w.varint(state.next_ball_id);
+            w.f32(state.accumulator);
+            w.f32(state.recent_balls_eaten);
+        }
+        Update::Delta(ref delta) => {
+            // Positions are fractions of its world, and colours are left out by it
+            let base = base.filter(|base| base.ticks == delta.base).ok_or(EncodeError::MissingBase(delta.base))?;
+            w.u8(DELTA);
+            w.varint(delta.base);
+            w.varint(delta.ticks - delta.base);
+            let mut has = 0;
+            if delta.ejected.is_some() { has |= HAS_EJECTED; }
+            if delta.viruses.is_some() { has |= HAS_VIRUSES; }
+            if delta.eaten_by.is_some() { has |= HAS_EATEN_BY; }
+            if delta.config.is_some() { has |= HAS_CONFIG; }
+            if delta.obstacles.is_some() { has |= HAS_OBSTACLES; }
+            w.u8(has);
+            if let Some(ref config) = delta.config { w.config(config); }
+            if let Some(ref obstacles) = delta.obstacles { w.obstacles(obstacles); }
+
+            let world = delta.config.as_ref().unwrap_or(&base.config).world_size;
+            let base = Some(base);
+            w.players(world, &delta.players, base);
+            w.ids(delta.removed_players.iter().map(|id| *id as u64));
+            w.names(&delta.names);
+            w.ids(delta.removed_names.iter().map(|id| *id as u64));
+            w.balls(world, &delta.created_balls, base);
+            w.ids(delta.removed_balls.iter().cloned());
+            if let Some(ref ejected) = delta.ejected { w.ejected(world, ejected, base); }
+            if let Some(ref viruses) = delta.viruses { w.viruses(world, viruses); }
+            if let Some(ref eaten_by) = delta.eaten_by { w.eaten_by(eaten_by); }
+            w.varint(delta.next_ball_id);
+            w.f32(delta.accumulator);
+            w.f32(delta.recent_balls_eaten);
+        }
+    }
+
+    w.varint(message.id as u64);
+    w.varint(message.acked);
+    w.varint(message.events.len() as u64);
+    for event in &message.events {
+        match *event {
+            GameEvent::PlayerEaten { victim, eater } => {
+                w.u8(0);
+                w.varint(victim as u64);
+                w.varint(eater as u64);
+            }
+            GameEvent::BallEaten { eater } => { w.u8(1); w.varint(eater as u64); }
+            GameEvent::PlayerSpawned { id } => { w.u8(2); w.varint(id as u64); }
+            GameEvent::PlayerLeft { id } => { w.u8(3); w.varint(id as u64); }
+        }
+    }
+    w.varint(message.leaderboard.len() as u64);
+    for &(id, mass) in &message.leaderboard {
+        w.varint(id as u64);
+        w.f32(mass);
+    }
+    w.varint(message.chat.len() as u64);
+    for chat in &message.chat {
+        w.varint(chat.id as u64);
+        w.string(&chat.name);
+        w.string(&chat.text);
+    }
+    match message.token {
+        Some(ref token) => { w.u8(1); w.string(token); }
+        None => w.u8(0),
+    }
+
+    Ok(w.bytes)
+}
+
+// Deltas are decoded against the states the receiver has
+pub fn decode_server_message(bytes: &[u8], receiver: &Receiver) -> Result<ServerMessage, DecodeError> {
+    let mut r = Reader { bytes, pos: 0 };
+
+    let update = match r.u8()? {
+        KEYFRAME => {
+            let ticks = r.varint()?;
+            let has_world = match r.u8()? {
+                0 => false,
+                1 => true,
+                tag => return Err(DecodeError::BadTag(tag)),
+            };
+            let (config, obstacles) = if has_world {
+                (r.config()?, r.obstacles()?)
+            } else {
+                // The ones we have, see delta::Keyframe
+                let newest = receiver.newest().ok_or(DecodeError::MissingWorld)?;
+                (newest.config.clone(), newest.obstacles.clone())
+            };
+            let base_ticks = match r.u8()? {
+                0 => None,
+                1 => Some(r.varint()?),
+                tag => return Err(DecodeError::BadTag(tag)),
+            };
+            let base = match base_ticks {
+                Some(ticks) => Some(receiver.base(ticks).ok_or(DecodeError::UnknownBase(ticks))?),
+                None => None,
+            };
+            let world = config.world_size;
+            let state = State {
+                players: r.players(world, base)?,
+                names: r.names()?,
+                balls: r.balls(world, base)?,
+                ejected: r.ejected(world, base)?,
+                viruses: r.viruses(world)?,
+                eaten_by: r.eaten_by()?,
+                // Not sent, see view::visible
+                rng: Rng::new(0),
+                next_ball_id: r.varint()?,
+                accumulator: r.f32()?,
+                recent_balls_eaten: r.f32()?,
+                ticks,
+                config,
+                obstacles,
+                events: Default::default(),
+            };
+            Update::Keyframe(Keyframe { state, world: has_world, base: base_ticks })
+        }
+        DELTA => {
+            let base_ticks = r.varint()?;
+            let base = receiver.base(base_ticks).ok_or(DecodeError::UnknownBase(base_ticks))?;
+            let ticks = base_ticks + r.varint()?;
+            let has = r.u8()?;
+            let config = if has & HAS_CONFIG != 0 { Some(r.config()?) } else { None };
+            let obstacles = if has & HAS_OBSTACLES != 0 { Some(r.obstacles()?) } else { None };
+
+            let world = config.as_ref().unwrap_or(&base.config).world_size;
+            Update::Delta(Delta {
+                base: base_ticks,
+                ticks,
+                players: r.players(world, Some(base))?,
+                removed_players: r.ids()?.into_iter().map(|id| id as usize).collect(),
+                names: r.names()?,
+                removed_names: r.ids()?.into_iter().map(|id| id as usize).collect(),
+                created_balls: r.balls(world, Some(base))?,
+                removed_balls: r.ids()?,
+                ejected: if has & HAS_EJECTED != 0 { Some(r.ejected(world, Some(base))?) } else { None },
+                viruses: if has & HAS_VIRUSES != 0 { Some(r.viruses(world)?) } else { None },
+                eaten_by: if has & HAS_EATEN_BY != 0 { Some(r.eaten_by()?) } else { None },
+                rng: base.rng.clone(),
+                next_ball_id: r.varint()?,
+                accumulator: r.f32()?,
+                recent_balls_eaten: r.f32()?,
+                config,
+                obstacles,
+            })
+        }
+        tag => return Err(DecodeError::BadTag(tag)),
+    };
+
+    let id = r.varint()? as usize;
+    let acked = r.varint()?;
+    let events = (0..r.varint()?).map(|_| {
+        Ok(match r.u8()? {
+            0 => GameEvent::PlayerEaten { victim: r.varint()? as usize, eater: r.varint()? as usize },
+            1 => GameEvent::BallEaten { eater: r.varint()? as usize },
+            2 => GameEvent::PlayerSpawned { id: r.varint()? as usize },
+            3 => GameEvent::PlayerLeft { id: r.varint()? as usize },
+            tag => return Err(DecodeError::BadTag(tag)),
+        })
+    }).collect::<Result<_, _>>()?;
+    let leaderboard = (0..r.varint()?)
+        .map(|_| Ok((r.varint()? as usize, r.f32()?)))
+        .collect::<Result<_, _>>()?;
+    let chat = (0..r.varint()?)
+        .map(|_| Ok(ChatMessage { id: r.varint()? as usize, name: r.string()?, text: r.string()? }))
+        .collect::<Result<_, _>>()?;
+    let token = match r.u8()? {
+        0 => None,
+        1 => Some(r.string()?),
+        tag => return Err(DecodeError::BadTag(tag)),
+    };
+    r.end()?;
+
+    Ok(ServerMessage { update, id, acked, events, leaderboard, chat, token })
+}
+
+pub fn encode_client_message(message: &ClientMessage) -> Vec<u8> {
+    let mut w = Writer::default();
+    match *message {
+        ClientMessage::Join(ref name) => { w.u8(0); w.string(name); }
+        ClientMessage::Resume(ref token) => { w.u8(1); w.string(token); }
+        ClientMessage::Command(seq, ref command) => {
+            w.u8(2);
+            w.varint(seq);
+            w.varint(command.id as u64);
+            match command.command {
+                PlayerCommand::SetDirectionAndSpeed(direction, speed) => {
+                    w.u8(0);
+                    w.f64(direction);
+                    w.f64(speed);
+                }
+                PlayerCommand::Split => w.u8(1),
+                PlayerCommand::EjectMass => w.u8(2),
+                PlayerCommand::Respawn => w.u8(3),
+            }
+        }
+        ClientMessage::Ack(ticks) => { w.u8(3); w.varint(ticks); }
+        ClientMessage::Chat(ref text) => { w.u8(4); w.string(text); }
+    }
+    w.bytes
+}
+
+pub fn decode_client_message(bytes: &[u8]) -> Result<ClientMessage, DecodeError> {
+    let mut r = Reader { bytes, pos: 0 };
+    let message = match r.u8()? {
+        0 => ClientMessage::Join(r.string()?),
+        1 => ClientMessage::Resume(r.string()?),
+        2 => {
+            let seq = r.varint()?;
+            let id = r.varint()? as usize;
+            let command = match r.u8()? {
+                0 => PlayerCommand::SetDirectionAndSpeed(r.f64()?, r.f64()?),
+                1 => PlayerCommand::Split,
+                2 => PlayerCommand::EjectMass,
+                3 => PlayerCommand::Respawn,
+                tag => return Err(DecodeError::BadTag(tag)),
+            };
+            ClientMessage::Command(seq, IdPlayerCommand { id, command })
+        }
+        3 => ClientMessage::Ack(r.varint()?),
+        4 => ClientMessage::Chat(r.string()?),
+        tag => return Err(DecodeError::BadTag(tag)),
+    };
+    r.end()?;
+    Ok(message)
+}
+
+// The colours the client has, by id
+fn player_color(base: &State, id: u64) -> Option<(u8, u8, u8)> {
+    base.players.get(&(id as usize)).map(|player| player.color)
+}
+
+fn ball_color(base: &State, id: u64) -> Option<(u8, u8, u8)> {
+    base.balls.binary_search_by_key(&id, |ball| ball.id).ok().map(|i| base.balls[i].color)
+}
+
+fn ejected_color(base: &State, id: u64) -> Option<(u8, u8, u8)> {
+    base.ejected.binary_search_by_key(&id, |ejected| ejected.id).ok().map(|i| base.ejected[i].color)
+}
+
+// Positions as fractions of the world, rounded to max steps
+fn quantize(pos: (f64, f64), world: (f64, f64), max: f64) -> (f64, f64) {
+    let q = |x: f64, size: f64| if size > 0. { (x / size).clamp(0., 1.) * max } else { 0. };
+    (q(pos.0, world.0).round(), q(pos.1, world.1).round())
+}
+
+fn unquantize(q: (f64, f64), world: (f64, f64), max: f64) -> (f64, f64) {
+    (q.0 / max * world.0, q.1 / max * world.1)
+}
+
+#[derive(Default)]
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, x: u8) {
+        self.bytes.push(x);
+    }
+
+    fn u16(&mut self, x: u16) {
+        self.bytes.extend_from_slice(&[x as u8, (x >> 8) as u8]);
+    }
+
+    fn u32(&mut self, x: u32) {
+        for i in 0..4 {
+            self.u8((x >> (8 * i)) as u8);
+        }
+    }
+
+    fn u64(&mut self, x: u64) {
+        for i in 0..8 {
+            self.u8((x >> (8 * i)) as u8);
+        }
+    }
+
+    fn f32(&mut self, x: f64) {
+        self.u32((x as f32).to_bits());
+    }
+
+    fn f64(&mut self, x: f64) {
+        self.u64(x.to_bits());
+    }
+
+    fn varint(&mut self, mut x: u64) {
+        while x >= 0x80 {
+            self.u8(x as u8 | 0x80);
+            x >>= 7;
+        }
+        self.u8(x as u8);
+    }
+
+    fn string(&mut self, s: &str) {
+        self.varint(s.len() as u64);
+        self.bytes.extend_from_slice(s.as_bytes());
+    }
+
+    fn color(&mut self, color: (u8, u8, u8)) {
+        self.bytes.extend_from_slice(&[color.0, color.1, color.2]);
+    }
+
+    // Left out if it's the one the client has, see the top of the file
+    fn known_color(&mut self, color: (u8, u8, u8), known: Option<(u8, u8, u8)>) {
+        if known == Some(color) {
+            self.u8(0);
+        } else {
+            self.u8(1);
+            self.color(color);
+        }
+    }
+
+    fn pos32(&mut self, pos: (f64, f64), world: (f64, f64)) {
+        let (x, y) = quantize(pos, world, u32::MAX as f64);
+        self.u32(x as u32);
+        self.u32(y as u32);
+    }
+
+    fn pos16(&mut self, pos: (f64, f64), world: (f64, f64)) {
+        let (x, y) = quantize(pos, world, u16::MAX as f64);
+        self.u16(x as u16);
+        self.u16(y as u16);
+    }
+
+    fn vel(&mut self, vel: (f64, f64)) {
+        self.f32(vel.0);
+        self.f32(vel.1);
+    }
+
+    // Ids in increasing order, which every caller has them in: they are sent as the differences
+    fn ids<I: ExactSizeIterator<Item=u64>>(&mut self, ids: I) {
+        self.varint(ids.len() as u64);
+        let mut last = 0;
+        for id in ids {
+            debug_assert!(id >= last, "Ids out of order");
+            self.varint(id - last);
+            last = id;
+        }
+    }
+
+    fn config(&mut self, config: &GameConfig) {
+        self.f64(config.world_size.0);
+        self.f64(config.world_size.1);
+        self.u8(match config.world_mode {
+            WorldMode::Walled => 0,
+            WorldMode::Wrapped => 1,
+        });
+        for x in &[config.start_size, config.speed_factor, config.grow_speed, config.size_ratio_to_eat,
+                   config.ball_density] {
+            self.f64(*x);
+        }
+        self.varint(config.max_balls as u64);
+        self.f64(config.ball_spawn_per_sec);
+        self.f64(config.ball_eaten_window);
+        self.varint(config.ball_kinds.len() as u64);
+        for kind in &config.ball_kinds {
+            self.f64(kind.size);
+            self.f64(kind.value);
+            self.f64(kind.weight);
+        }
+        self.f64(config.min_split_size);
+        self.varint(config.max_cells as u64);
+        for x in &[config.split_speed, config.split_friction, config.merge_time, config.min_eject_size,
+                   config.eject_area_loss, config.eject_area, config.eject_speed, config.eject_friction] {
+            self.f64(*x);
+        }
+        self.varint(config.virus_count as u64);
+        for x in &[config.virus_size, config.virus_max_size, config.virus_speed, config.virus_friction] {
+            self.f64(*x);
+        }
+        self.varint(config.virus_pop_pieces as u64);
+    }
+
+    fn obstacles(&mut self, obstacles: &[Obstacle]) {
+        self.varint(obstacles.len() as u64);
+        for obstacle in obstacles {
+            match *obstacle {
+                Obstacle::Circle { center, radius } => {
+                    self.u8(0);
+                    self.f64(center.0);
+                    self.f64(center.1);
+                    self.f64(radius);
+                }
+                Obstacle::Rect { min, max } => {
+                    self.u8(1);
+                    for x in &[min.0, min.1, max.0, max.1] {
+                        self.f64(*x);
+                    }
+                }
+                Obstacle::Polygon { ref points } => {
+                    self.u8(2);
+                    self.varint(points.len() as u64);
+                    for point in points {
+                        self.f64(point.0);
+                        self.f64(point.1);
+                    }
+                }
+            }
+        }
+    }
+
+    fn players(&mut self, world: (f64, f64), players: &BTreeMap<usize, Player>, base: Option<&State>) {
+        self.ids(players.keys().map(|id| *id as u64));
+        for (id, player) in players {
+            self.known_color(player.color, base.and_then(|base| player_color(base, *id as u64)));
+            self.f32(player.direction);
+            self.f32(player.speed);
+            self.varint(player.cells.len() as u64);
+            for cell in &player.cells {
+                self.pos32(cell.pos, world);
+                self.f32(cell.size);
+                self.f32(cell.show_size);
+                self.vel(cell.vel);
+                self.f32(cell.merge_timer);
+            }
+        }
+    }
+
+    fn names(&mut self, names: &BTreeMap<usize, String>) {
+        self.ids(names.keys().map(|id| *id as u64));
+        for name in names.values() {
+            self.string(name);
+        }
+    }
+
+    fn balls(&mut self, world: (f64, f64), balls: &[Ball], base: Option<&State>) {
+        self.ids(balls.iter().map(|ball| ball.id));
+        for ball in balls {
+            self.pos16(ball.pos, world);
+            self.known_color(ball.color, base.and_then(|base| ball_color(base, ball.id)));
+            self.f32(ball.size);
+            self.f32(ball.value);
+        }
+    }
+
+    fn ejected(&mut self, world: (f64, f64), ejected: &[EjectedMass], base: Option<&State>) {
+        self.ids(ejected.iter().map(|ejected| ejected.id));
+        for ejected in ejected {
+            self.pos32(ejected.pos, world);
+            self.vel(ejected.vel);
+            self.f32(ejected.size);
+            self.known_color(ejected.color, base.and_then(|base| ejected_color(base, ejected.id)));
+        }
+    }
+
+    fn viruses(&mut self, world: (f64, f64), viruses: &[Virus]) {
+        self.varint(viruses.len() as u64);
+        for virus in viruses {
+            self.pos32(virus.pos, world);
+            self.vel(virus.vel);
+            self.f32(virus.size);
+        }
+    }
+
+    fn eaten_by(&mut self, eaten_by: &BTreeMap<usize, usize>) {
+        self.ids(eaten_by.keys().map(|id| *id as u64));
+        for eater in eaten_by.values() {
+            self.varint(*eater as u64);
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], DecodeError> {
+        if self.bytes.len() - self.pos < n {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        self.pos += n;
+        Ok(&self.bytes[self.pos - n..self.pos])
+    }
+
+    fn end(&self) -> Result<(), DecodeError> {
+        if self.pos == self.bytes.len() { Ok(()) } else { Err(DecodeError::TrailingBytes) }
+    }
+
+    fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn uint(&mut self, n: usize) -> Result<u64, DecodeError> {
+        Ok(self.take(n)?.iter().rev().fold(0, |x, byte| x << 8 | u64::from(*byte)))
+    }
+
+    fn u64(&mut self) -> Result<u64, DecodeError> {
+        self.uint(8)
+    }
+
+    fn f32(&mut self) -> Result<f64, DecodeError> {
+        Ok(f64::from(f32::from_bits(self.uint(4)? as u32)))
+    }
+
+    fn f64(&mut self) -> Result<f64, DecodeError> {
+        Ok(f64::from_bits(self.u64()?))
+    }
+
+    fn varint(&mut self) -> Result<u64, DecodeError> {
+        let mut x = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            x |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(x);
+            }
+        }
+        Err(DecodeError::BadVarint)
+    }
+
+    // For counts, which can't be more than there are bytes left. Keeps a bad count from
+    // allocating a lot.
+    fn len(&mut self) -> Result<usize, DecodeError> {
+        let len = self.varint()?;
+        if len > (self.bytes.len() - self.pos) as u64 {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        Ok(len as usize)
+    }
+
+    fn string(&mut self) -> Result<String, DecodeError> {
+        let len = self.len()?;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| DecodeError::BadString)
+    }
+
+    fn color(&mut self) -> Result<(u8, u8, u8), DecodeError> {
+        let bytes = self.take(3)?;
+        Ok((bytes[0], bytes[1], bytes[2]))
+    }
+
+    // Colours left out are the ones in base
+    fn known_color(&mut self, id: u64, known: Option<(u8, u8, u8)>) -> Result<(u8, u8, u8), DecodeError> {
+        match self.u8()? {
+            0 => known.ok_or(DecodeError::MissingColor(id)),
+            1 => self.color(),
+            tag => Err(DecodeError::BadTag(tag)),
+        }
+    }
+
+    fn pos32(&mut self, world: (f64, f64)) -> Result<(f64, f64), DecodeError> {
+        let q = (self.uint(4)? as f64, self.uint(4)? as f64);
+        Ok(unquantize(q, world, u32::MAX as f64))
+    }
+
+    fn pos16(&mut self, world: (f64, f64)) -> Result<(f64, f64), DecodeError> {
+        let q = (self.uint(2)? as f64, self.uint(2)? as f64);
+        Ok(unquantize(q, world, u16::MAX as f64))
+    }
+
+    fn vel(&mut self) -> Result<(f64, f64), DecodeError> {
+        Ok((self.f32()?, self.f32()?))
+    }
+
+    fn ids(&mut self) -> Result<Vec<u64>, DecodeError> {
+        let mut last = 0u64;
+        (0..self.len()?).map(|_| {
+            last = last.checked_add(self.varint()?).ok_or(DecodeError::BadVarint)?;
+            Ok(last)
+        }).collect()
+    }
+
+    fn config(&mut self) -> Result<GameConfig, DecodeError> {
+        Ok(GameConfig {
+            world_size: (self.f64()?, self.f64()?),
+            world_mode: match self.u8()? {
+                0 => WorldMode::Walled,
+                1 => WorldMode::Wrapped,
+                tag => return Err(DecodeError::BadTag(tag)),
+            },
+            start_size: self.f64()?,
+            speed_factor: self.f64()?,
+            grow_speed: self.f64()?,
+            size_ratio_to_eat: self.f64()?,
+            ball_density: self.f64()?,
+            max_balls: self.varint()? as usize,
+            ball_spawn_per_sec: self.f64()?,
+            ball_eaten_window: self.f64()?,
+            ball_kinds: (0..self.len()?)
+                .map(|_| Ok(BallKind { size: self.f64()?, value: self.f64()?, weight: self.f64()? }))
+                .collect::<Result<_, _>>()?,
+            min_split_size: self.f64()?,
+            max_cells: self.varint()? as usize,
+            split_speed: self.f64()?,
+            split_friction: self.f64()?,
+            merge_time: self.f64()?,
+            min_eject_size: self.f64()?,
+            eject_area_loss: self.f64()?,
+            eject_area: self.f64()?,
+            eject_speed: self.f64()?,
+            eject_friction: self.f64()?,
+            virus_count: self.varint()? as usize,
+            virus_size: self.f64()?,
+            virus_max_size: self.f64()?,
+            virus_speed: self.f64()?,
+            virus_friction: self.f64()?,
+            virus_pop_pieces: self.varint()? as usize,
+        })
+    }
+
+    fn obstacles(&mut self) -> Result<Vec<Obstacle>, DecodeError> {
+        (0..self.len()?).map(|_| {
+            Ok(match self.u8()? {
+                0 => Obstacle::Circle { center: (self.f64()?, self.f64()?), radius: self.f64()? },
+                1 => Obstacle::Rect { min: (self.f64()?, self.f64()?), max: (self.f64()?, self.f64()?) },
+                2 => Obstacle::Polygon {
+                    points: (0..self.len()?).map(|_| Ok((self.f64()?, self.f64()?))).collect::<Result<_, _>>()?,
+                },
+                tag => return Err(DecodeError::BadTag(tag)),
+            })
+        }).collect()
+    }
+
+    fn players(&mut self, world: (f64, f64), base: Option<&State>) -> Result<BTreeMap<usize, Player>, DecodeError> {
+        let ids = self.ids()?;
+        ids.into_iter().map(|id| {
+            let color = self.known_color(id, base.and_then(|base| player_color(base, id)))?;
+            let direction = self.f32()?;
+            let speed = self.f32()?;
+            let cells = (0..self.len()?).map(|_| {
+                Ok(Cell {
+                    pos: self.pos32(world)?,
+                    size: self.f32()?,
+                    show_size: self.f32()?,
+                    vel: self.vel()?,
+                    merge_timer: self.f32()?,
+                })
+            }).collect::<Result<_, _>>()?;
+            Ok((id as usize, Player { cells, direction, speed, color }))
+        }).collect()
+    }
+
+    fn names(&mut self) -> Result<BTreeMap<usize, String>, DecodeError> {
+        let ids = self.ids()?;
+        ids.into_iter().map(|id| Ok((id as usize, self.string()?))).collect()
+    }
+
+    fn balls(&mut self, world: (f64, f64), base: Option<&State>) -> Result<Vec<Ball>, DecodeError> {
+        let ids = self.ids()?;
+        ids.into_iter().map(|id| {
+            Ok(Ball {
+                id,
+                pos: self.pos16(world)?,
+                color: self.known_color(id, base.and_then(|base| ball_color(base, id)))?,
+                size: self.f32()?,
+                value: self.f32()?,
+            })
+        }).collect()
+    }
+
+    fn ejected(&mut self, world: (f64, f64), base: Option<&State>) -> Result<Vec<EjectedMass>, DecodeError> {
+        let ids = self.ids()?;
+        ids.into_iter().map(|id| {
+            Ok(EjectedMass {
+                id,
+                pos: self.pos32(world)?,
+                vel: self.vel()?,
+                size: self.f32()?,
+                color: self.known_color(id, base.and_then(|base| ejected_color(base, id)))?,
+            })
+        }).collect()
+    }
+
+    fn viruses(&mut self, world: (f64, f64)) -> Result<Vec<Virus>, DecodeError> {
+        (0..self.len()?).map(|_| {
+            Ok(Virus { pos: self.pos32(world)?, vel: self.vel()?, size: self.f32()? })
+        }).collect()
+    }
+
+    fn eaten_by(&mut self) -> Result<BTreeMap<usize, usize>, DecodeError> {
+        let ids = self.ids()?;
+        ids.into_iter().map(|id| Ok((id as usize, self.varint()? as usize))).collect()
+    }
+}
+
+#[test]
+fn test_client_messages() {
+    let messages = vec![
+        ClientMessage::Join("ünïcode".to_string()),
+        ClientMessage::Resume("token".to_string()),
+        ClientMessage::Command(300, IdPlayerCommand { id: 7, command: PlayerCommand::SetDirectionAndSpeed(-1.25, 0.1) }),
+        ClientMessage::Command(1, IdPlayerCommand { id: 1 << 40, command: PlayerCommand::Respawn }),
+        ClientMessage::Ack(u64::MAX),
+        ClientMessage::Chat("hi".to_string()),
+    ];
+    for message in messages {
+        let bytes = encode_client_message(&message);
+        assert_eq!(format!("{:?}", decode_client_message(&bytes).unwrap()), format!("{:?}", message));
+        assert_eq!(decode_client_message(&bytes[..bytes.len() - 1]).err(), Some(DecodeError::UnexpectedEnd));
+    }
+    assert_eq!(decode_client_message(&[9]).err(), Some(DecodeError::BadTag(9)));
+}
+
+#[test]
+fn test_server_messages() {
+    use bots::Bots;
+    use delta::Sender;
+
+    let mut state = State::with_config(3, GameConfig { world_size: (2000., 2000.), ..GameConfig::default() });
+    state.obstacles.push(Obstacle::Rect { min: (100., 100.), max: (200., 150.) });
+    state.fill_balls();
+    let mut bots = Bots::new();
+    bots.keep_population(&mut state, 10, |state| (1..).find(|id| !state.names.contains_key(id)).unwrap());
+
+    let close = |a: (f64, f64), b: (f64, f64), max: f64| {
+        (a.0 - b.0).abs() <= 2000. / max && (a.1 - b.1).abs() <= 2000. / max
+    };
+
+    let mut sender = Sender::new();
+    let mut receiver = Receiver::new();
+    let (mut binary, mut json, mut keyframes_without_world, mut keyframes_with_base) = (0, 0, 0, 0);
+    for _ in 0..60 {
+        for _ in 0..6 {
+            bots.command_all(&mut state);
+            state.tick(::TICK_DT);
+        }
+        let events = state.take_events();
+
+        let update = sender.update(&state);
+        let base = update.base().and_then(|ticks| sender.base(ticks));
+        let message = ServerMessage {
+            update,
+            id: 1,
+            acked: 12,
+            events,
+            leaderboard: state.leaderboard(10),
+            chat: vec![ChatMessage { id: 1, name: "bot".to_string(), text: "hello".to_string() }],
+            token: Some("token".to_string()),
+        };
+        let bytes = encode_server_message(&message, base).unwrap();
+        binary += bytes.len();
+        json += ::serde_json::to_vec(&message.update).unwrap().len();
+
+        let decoded = decode_server_message(&bytes, &receiver).unwrap();
+        assert_eq!((decoded.id, decoded.acked, &decoded.events, &decoded.chat, &decoded.token),
+                   (message.id, message.acked, &message.events, &message.chat, &message.token));
+
+        // Decoding again what was decoded once changes nothing
+        if let Update::Keyframe(ref keyframe) = decoded.update {
+            let base = keyframe.base.and_then(|ticks| receiver.base(ticks));
+            assert_eq!(encode_server_message(&decoded, base).unwrap(), bytes);
+            if !keyframe.world { keyframes_without_world += 1; }
+            if base.is_some() { keyframes_with_base += 1; }
+        }
+
+        let received = receiver.receive(decoded.update).unwrap();
+        assert_eq!(received.ticks, state.ticks);
+        assert_eq!(received.config, state.config);
+        assert_eq!(received.obstacles, state.obstacles);
+        assert_eq!(received.names, state.names);
+        // Clients don't get the rng, see view::visible
+        assert_eq!(received.rng, Rng::new(0));
+        assert_eq!(received.players.keys().collect::<Vec<_>>(), state.players.keys().collect::<Vec<_>>());
+        for (id, player) in &state.players {
+            let got = &received.players[id];
+            assert_eq!(got.color, player.color);
+            assert_eq!(got.cells.len(), player.cells.len());
+            for (a, b) in got.cells.iter().zip(player.cells.iter()) {
+                assert!(close(a.pos, b.pos, u32::MAX as f64));
+                assert!((a.size - b.size).abs() < 1e-3);
+            }
+        }
+        assert_eq!(received.balls.len(), state.balls.len());
+        for (a, b) in received.balls.iter().zip(state.balls.iter()) {
+            assert_eq!((a.id, a.color), (b.id, b.color));
+            assert!(close(a.pos, b.pos, u16::MAX as f64));
+        }
+        assert_eq!(received.ejected.iter().map(|e| (e.id, e.color)).collect::<Vec<_>>(),
+                   state.ejected.iter().map(|e| (e.id, e.color)).collect::<Vec<_>>());
+        sender.ack(received.ticks);
+    }
+
+    assert!(binary * 4 < json);
+    // Later keyframes go without the config and obstacles the client kept
+    assert!(keyframes_without_world > 0);
+    // And without the colours the client has
+    assert!(keyframes_with_base > 0);
+
+    // Players the client already has are sent without their colour
+    let id = state.next_ball_id;
+    state.ejected.push(EjectedMass { id, pos: (10., 10.), vel: (0., 0.), size: 5., color: (1, 2, 3) });
+    let mut known = state.clone();
+    known.ticks -= 1;
+    for player in known.players.values_mut() {
+        player.direction += 1.;
+    }
+    let message = ServerMessage {
+        update: Update::Delta(Delta::between(&known, &state)),
+        id: 1, acked: 0, events: vec![], leaderboard: vec![], chat: vec![], token: None,
+    };
+    let mut unknown = known.clone();
+    for player in unknown.players.values_mut() {
+        player.color.0 = player.color.0.wrapping_add(1);
+    }
+    for ball in &mut unknown.balls {
+        ball.color.0 = ball.color.0.wrapping_add(1);
+    }
+    for ejected in &mut unknown.ejected {
+        ejected.color.0 = ejected.color.0.wrapping_add(1);
+    }
+    assert!(!state.players.is_empty());
+    assert_eq!(encode_server_message(&message, Some(&known)).unwrap().len() + 3 * state.players.len(),
+               encode_server_message(&message, Some(&unknown)).unwrap().len());
+    assert_eq!(decode_server_message(&encode_server_message(&message, Some(&known)).unwrap(), &Receiver::new()).err(),
+               Some(DecodeError::UnknownBase(known.ticks)));
+
+    // So are balls and ejected mass in a keyframe
+    let message = ServerMessage {
+        update: Update::Keyframe(Keyframe { state: state.clone(), world: false, base: Some(known.ticks) }),
+        ..message
+    };
+    let entities = state.players.len() + state.balls.len() + state.ejected.len();
+    assert_eq!(encode_server_message(&message, Some(&known)).unwrap().len() + 3 * entities,
+               encode_server_message(&message, Some(&unknown)).unwrap().len());
+}
+
+#[test]
+fn test_deltas_on_a_big_world_without_acks() {
+    use delta::Sender;
+
+    let mut state = State::with_config(0, GameConfig { world_size: (4000., 4000.), ..GameConfig::default() });
+    state.join(1, "far".to_string());
+
+    // Acknowledged once, then not for longer than the server remembers states
+    let mut sender = Sender::new();
+    let mut receiver = Receiver::new();
+    let mut deltas = 0;
+    for update in 0..40 {
+        let pos = (3000., 3000. - update as f64);
+        state.players.get_mut(&1).unwrap().cells[0].pos = pos;
+        state.ticks += 1;
+
+        let update = sender.update(&state);
+        if let Update::Delta(_) = update { deltas += 1; }
+        let base = update.base().and_then(|ticks| sender.base(ticks));
+        let message = ServerMessage { update, id: 1, acked: 0, events: vec![], leaderboard: vec![], chat: vec![], token: None };
+        let bytes = encode_server_message(&message, base).unwrap();
+
+        let received = receiver.receive(decode_server_message(&bytes, &receiver).unwrap().update).unwrap();
+        let got = received.players[&1].cells[0].pos;
+        assert!((got.0 - pos.0).abs() < 1e-3 && (got.1 - pos.1).abs() < 1e-3);
+        if deltas == 0 {
+            sender.ack(received.ticks);
+        }
+    }
+    assert!(deltas > 0);
+
+    // Deltas can't be encoded without their base
+    let base = state.clone();
+    state.ticks += 1;
+    let message = ServerMessage {
+        update: Update::Delta(Delta::between(&base, &state)),
+        id: 1, acked: 0, events: vec![], leaderboard: vec![], chat: vec![], token: None,
+    };
+    assert_eq!(encode_server_message(&message, None), Err(EncodeError::MissingBase(base.ticks)));
+}
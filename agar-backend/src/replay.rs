@@ -0,0 +1,197 @@
+// Recording games so they can be simulated again, exactly.
+//
+// A recording is a header followed by every Input that changed the game, each tagged with
+// State::ticks at the time it was applied. Starting from the header's state and running the
+// same ticks with the same inputs in between gives the same State, see the determinism
+// notes on State::with_seed.
+//
+// Files are a sequence of frames, each a little endian u32 length and that many bytes of the
+// serialized header or (tick, Input). The serialization is up to the user, so the recording
+// can be written as it goes and survives the server going down.
+
+use std::io::{self, Read, Write};
+
+use {State, GameConfig, Obstacle, IdPlayerCommand};
+
+// Changes whenever recordings from before can't be replayed anymore
+pub const RECORDING_VERSION: u32 = 2;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RecordingHeader {
+    pub version: u32,
+    pub seed: u64,
+    pub config: GameConfig,
+    pub obstacles: Vec<Obstacle>,
+    // For games that didn't start from scratch, like ones resumed from a snapshot
+    #[serde(default)]
+    pub start: Option<State>,
+}
+
+impl RecordingHeader {
+    pub fn new(seed: u64, config: GameConfig, obstacles: Vec<Obstacle>) -> RecordingHeader {
+        RecordingHeader { version: RECORDING_VERSION, seed, config, obstacles, start: None }
+    }
+
+    pub fn from_state(state: &State) -> RecordingHeader {
+        RecordingHeader {
+            start: Some(state.clone()),
+            ..RecordingHeader::new(0, state.config.clone(), state.obstacles.clone())
+        }
+    }
+
+    // The state a recorded game starts from. Servers should start from this too, even when
+    // not recording.
+    pub fn start_state(&self) -> State {
+        if let Some(ref start) = self.start {
+            return start.clone();
+        }
+
+        let mut state = State::with_config(self.seed, self.config.clone());
+        state.obstacles = self.obstacles.clone();
+        state.fill_balls();
+        state
+    }
+}
+
+// Everything from outside the simulation that changes the game
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Input {
+    Join { id: usize, name: String },
+    Leave { id: usize },
+    Command(IdPlayerCommand),
+}
+
+impl Input {
+    pub fn apply(self, state: &mut State) {
+        match self {
+            Input::Join { id, name } => state.join(id, name),
+            Input::Leave { id } => { state.remove_player(id); }
+            Input::Command(command) => state.do_command(command),
+        }
+    }
+}
+
+// Runs the recorded game up to the given tick, or up to the last input. Inputs have to be in
+// the order they were recorded in. How much time the server had left over towards the next
+// tick isn't recorded, so State::accumulator ends up zero.
+pub fn replay<I: IntoIterator<Item = (u64, Input)>>(header: &RecordingHeader, inputs: I, until: Option<u64>) -> State {
+    let mut state = header.start_state();
+
+    for (tick, input) in inputs {
+        if until.map(|until| tick > until).unwrap_or(false) {
+            break;
+        }
+        while state.ticks < tick {
+            state.tick(::TICK_DT);
+        }
+        input.apply(&mut state);
+    }
+
+    if let Some(until) = until {
+        while state.ticks < until {
+            state.tick(::TICK_DT);
+        }
+    }
+    state.accumulator = 0.;
+    state
+}
+
+pub fn write_frame<W: Write>(writer: &mut W, bytes: &[u8]) -> io::Result<()> {
+    writer.write_all(&(bytes.len() as u32).to_le_bytes())?;
+    writer.write_all(bytes)
+}
+
+// None at the end of the file. A frame cut off by the writer going down also counts as the
+// end.
+pub fn read_frame<R: Read>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut len = [0; 4];
+    match reader.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    let mut bytes = vec![0; u32::from_le_bytes(len) as usize];
+    match reader.read_exact(&mut bytes) {
+        Ok(()) => Ok(Some(bytes)),
+        Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+#[test]
+fn test_frames() {
+    let mut file = vec![];
+    write_frame(&mut file, b"header").unwrap();
+    write_frame(&mut file, b"").unwrap();
+    write_frame(&mut file, b"last").unwrap();
+    // Cut off in the middle of a frame
+    write_frame(&mut file, b"lost").unwrap();
+    file.pop();
+
+    let mut reader = &file[..];
+    assert_eq!(read_frame(&mut reader).unwrap(), Some(b"header".to_vec()));
+    assert_eq!(read_frame(&mut reader).unwrap(), Some(vec![]));
+    assert_eq!(read_frame(&mut reader).unwrap(), Some(b"last".to_vec()));
+    assert_eq!(read_frame(&mut reader).unwrap(), None);
+}
+
+#[test]
+fn test_replay_gives_the_same_state() {
+    use bots::Bots;
+    use PlayerCommand;
+
+    let header = RecordingHeader::new(11, GameConfig::default(), vec![]);
+
+    // Play a game like a server would, recording every input to a file. The server goes on
+    // by however much time passed, which is never quite a whole number of ticks.
+    let mut file = vec![];
+    write_frame(&mut file, &::serde_json::to_vec(&header).unwrap()).unwrap();
+
+    let mut state = header.start_state();
+    let mut bots = Bots::new();
+    for frame in 0..1200u64 {
+        // Bots apply their inputs themselves
+        let mut applied = bots.keep_population(&mut state, if frame < 600 { 6 } else { 4 }, |state| {
+            (100..).find(|id| !state.names.contains_key(id)).unwrap()
+        });
+        applied.extend(bots.command_all(&mut state));
+
+        let human = match frame {
+            60 => Some(Input::Join { id: 1, name: "human".to_string() }),
+            90 => Some(Input::Command(IdPlayerCommand { id: 1, command: PlayerCommand::SetDirectionAndSpeed(1., 5.) })),
+            240 => Some(Input::Command(IdPlayerCommand { id: 1, command: PlayerCommand::Split })),
+            900 => Some(Input::Leave { id: 1 }),
+            _ => None,
+        };
+        if let Some(input) = human {
+            applied.push(input.clone());
+            input.apply(&mut state);
+        }
+
+        for input in applied {
+            write_frame(&mut file, &::serde_json::to_vec(&(state.ticks, &input)).unwrap()).unwrap();
+        }
+        state.advance(::TICK_DT * (0.5 + (frame % 7) as f64 * 0.4));
+    }
+    state.take_events();
+    assert!(state.accumulator > 0.);
+
+    // And read it back
+    let mut reader = &file[..];
+    let header: RecordingHeader = ::serde_json::from_slice(&read_frame(&mut reader).unwrap().unwrap()).unwrap();
+    let mut inputs: Vec<(u64, Input)> = vec![];
+    while let Some(frame) = read_frame(&mut reader).unwrap() {
+        inputs.push(::serde_json::from_slice(&frame).unwrap());
+    }
+
+    let mut replayed = replay(&header, inputs.clone(), Some(state.ticks));
+    replayed.take_events();
+    state.accumulator = 0.;
+    assert_eq!(replayed, state);
+
+    // Stopping early
+    let halfway = replay(&header, inputs, Some(700));
+    assert_eq!(halfway.ticks, 700);
+    assert!(halfway.players.contains_key(&1));
+}
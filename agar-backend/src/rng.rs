@@ -0,0 +1,66 @@
+// The random number generator owned by State, used for everything that gets spawned.
+//
+// This is SplitMix64, which is tiny, fast, works with any seed and needs nothing from the
+// platform, so it behaves the same on the server and in the wasm client. It is part of the
+// serialized State so a saved game continues with the same random numbers.
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    // The seed that makes an Rng continue where this one is, for sending it along
+    pub fn seed(&self) -> u64 {
+        self.state
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // Uniform in [0, 1)
+    pub fn gen_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // Uniform in [low, high)
+    pub fn gen_range(&mut self, low: f64, high: f64) -> f64 {
+        low + self.gen_f64() * (high - low)
+    }
+
+    pub fn gen_color(&mut self) -> (u8, u8, u8) {
+        let x = self.next_u64();
+        (x as u8, (x >> 8) as u8, (x >> 16) as u8)
+    }
+}
+
+#[test]
+fn test_same_seed_same_numbers() {
+    let mut a = Rng::new(1234);
+    let mut b = Rng::new(1234);
+    let mut c = Rng::new(1235);
+
+    let xs: Vec<u64> = (0..100).map(|_| a.next_u64()).collect();
+    let ys: Vec<u64> = (0..100).map(|_| b.next_u64()).collect();
+    let zs: Vec<u64> = (0..100).map(|_| c.next_u64()).collect();
+    assert_eq!(xs, ys);
+    assert_ne!(xs, zs);
+}
+
+#[test]
+fn test_gen_range() {
+    let mut rng = Rng::new(0);
+    for _ in 0..1000 {
+        let x = rng.gen_range(-3., 5.);
+        assert!((-3. ..5.).contains(&x));
+    }
+}
@@ -0,0 +1,76 @@
+// Saving a running game to disk, so a restarted server can carry on with it.
+//
+// Like recordings, the serialization is up to the user. Check SnapshotVersion first, as a
+// snapshot from another version won't deserialize, or worse, will deserialize wrong.
+
+use std::collections::BTreeMap;
+
+use State;
+use bots::Bots;
+
+// Changes whenever snapshots from before can't be loaded anymore
+pub const SNAPSHOT_VERSION: u32 = 2;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub version: u32,
+    pub state: State,
+    pub bots: Bots,
+    // Session token: player id, for players to get their cells back after the restart
+    pub sessions: BTreeMap<String, usize>,
+}
+
+// Just the version of a snapshot, which reads from any snapshot
+#[derive(Deserialize, Debug)]
+pub struct SnapshotVersion {
+    pub version: u32,
+}
+
+impl Snapshot {
+    // Only sessions of players in the game are kept
+    pub fn new(state: State, bots: Bots, mut sessions: BTreeMap<String, usize>) -> Snapshot {
+        sessions.retain(|_, id| state.names.contains_key(id));
+        Snapshot { version: SNAPSHOT_VERSION, state, bots, sessions }
+    }
+
+    // The players that were in the game but aren't bots, who have to reclaim their sessions
+    pub fn humans(&self) -> Vec<usize> {
+        self.state.names.keys().cloned().filter(|id| !self.bots.is_bot(*id)).collect()
+    }
+}
+
+#[test]
+fn test_resumed_game_carries_on() {
+    let mut state = State::with_seed(5);
+    state.fill_balls();
+    let mut bots = Bots::new();
+    bots.keep_population(&mut state, 4, |state| (2..).find(|id| !state.names.contains_key(id)).unwrap());
+    state.join(1, "human".to_string());
+    for _ in 0..500 {
+        bots.command_all(&mut state);
+        state.tick(::TICK_DT);
+    }
+
+    let mut sessions = BTreeMap::new();
+    sessions.insert("human's".to_string(), 1);
+    sessions.insert("gone".to_string(), 42);
+    let json = ::serde_json::to_vec(&Snapshot::new(state.clone(), bots.clone(), sessions)).unwrap();
+
+    let version: SnapshotVersion = ::serde_json::from_slice(&json).unwrap();
+    assert_eq!(version.version, SNAPSHOT_VERSION);
+    let snapshot: Snapshot = ::serde_json::from_slice(&json).unwrap();
+    assert_eq!(snapshot.sessions.iter().collect::<Vec<_>>(), vec![(&"human's".to_string(), &1)]);
+    assert_eq!(snapshot.humans(), vec![1]);
+
+    // Both games go on the same way
+    let (mut resumed, resumed_bots) = (snapshot.state, snapshot.bots);
+    for _ in 0..500 {
+        bots.command_all(&mut state);
+        state.tick(::TICK_DT);
+        resumed_bots.command_all(&mut resumed);
+        resumed.tick(::TICK_DT);
+    }
+    state.take_events();
+    resumed.take_events();
+    assert_eq!(resumed, state);
+}
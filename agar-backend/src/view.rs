@@ -0,0 +1,173 @@
+// What a client can see. The client's camera follows its player, or whoever ate it, and shows
+// more of the world the bigger that player is and the further out the camera is zoomed. The
+// server only sends what is inside the largest view the client could have, so clients don't
+// get, and can't show, the rest of the world.
+
+use {State, GameConfig, GameEvent, Rng};
+
+// Limits of the zoom the player picks by scrolling
+pub const MIN_ZOOM: f64 = 0.4;
+pub const MAX_ZOOM: f64 = 3.;
+const ZOOM_SCALE: f64 = 0.010;
+// On top of the view, for the camera lagging behind and things moving in between updates
+const VIEW_MARGIN: f64 = 50.;
+
+// Pixels per unit of world, for a player of the given size on a screen of the given size
+pub fn zoom(zoom: f64, size: f64, screen: (f64, f64)) -> f64 {
+    ZOOM_SCALE * zoom / (size.sqrt() + 2.) * (screen.0 + screen.1)
+}
+
+// A square around what the camera looks at
+#[derive(Debug, Clone, PartialEq)]
+pub struct View {
+    pub center: (f64, f64),
+    pub half_size: f64,
+}
+
+impl View {
+    // The most the client of player id could see, on any screen. Clients without a player
+    // look at the middle of the world.
+    pub fn of(state: &State, id: usize) -> View {
+        let followed = eaten_by(state, id).last().map(|&(_, eater)| eater).unwrap_or(id);
+
+        let (center, size) = match state.players.get(&followed) {
+            Some(player) => (player.center(&state.config), player.size()),
+            None => ((state.config.world_size.0 / 2., state.config.world_size.1 / 2.), state.config.start_size),
+        };
+
+        // Each side of the screen is at most screen.0 + screen.1 pixels
+        let half_size = 0.5 / zoom(MIN_ZOOM, size, (1., 0.)) + VIEW_MARGIN;
+        View { center, half_size }
+    }
+
+    pub fn contains(&self, config: &GameConfig, pos: (f64, f64), radius: f64) -> bool {
+        let (dx, dy) = config.delta(self.center, pos);
+        dx.abs() <= self.half_size + radius && dy.abs() <= self.half_size + radius
+    }
+}
+
+// Who ate player id, who ate them, and so on, as (victim, eater)
+fn eaten_by(state: &State, id: usize) -> Vec<(usize, usize)> {
+    let mut chain = vec![];
+    let mut victim = id;
+    // Bounded in case the chain somehow loops, like the client's
+    for _ in 0..state.eaten_by.len() {
+        match state.eaten_by.get(&victim) {
+            Some(eater) => {
+                chain.push((victim, *eater));
+                victim = *eater;
+            }
+            None => break,
+        }
+    }
+    chain
+}
+
+// The state as the client of player id may see it: players with a cell in view, and the balls,
+// ejected mass and viruses in view. Of eaten_by only the chain the camera follows, and no rng,
+// so the client can't tell what spawns next. Names and the rest are kept whole.
+pub fn visible(state: &State, id: usize) -> State {
+    let view = View::of(state, id);
+    let config = &state.config;
+
+    State {
+        players: state.players.iter()
+            .filter(|(_, player)| player.cells.iter().any(|cell| view.contains(config, cell.pos, cell.size)))
+            .map(|(id, player)| (*id, player.clone()))
+            .collect(),
+        balls: state.balls.iter().filter(|ball| view.contains(config, ball.pos, ball.size)).cloned().collect(),
+        ejected: state.ejected.iter().filter(|ejected| view.contains(config, ejected.pos, ejected.size)).cloned().collect(),
+        viruses: state.viruses.iter().filter(|virus| view.contains(config, virus.pos, virus.size)).cloned().collect(),
+        names: state.names.clone(),
+        config: state.config.clone(),
+        obstacles: state.obstacles.clone(),
+        eaten_by: eaten_by(state, id).into_iter().collect(),
+        rng: Rng::new(0),
+        next_ball_id: state.next_ball_id,
+        ticks: state.ticks,
+        accumulator: state.accumulator,
+        recent_balls_eaten: state.recent_balls_eaten,
+        events: Default::default(),
+    }
+}
+
+// The events the client of player id may hear about: those about itself, and about players
+// in seen, what visible gave it now, or in before, what it gave the client the time before.
+pub fn visible_events(events: Vec<GameEvent>, id: usize, seen: &State, before: Option<&State>) -> Vec<GameEvent> {
+    let sees = |player: usize| {
+        player == id
+            || seen.players.contains_key(&player)
+            || before.map(|before| before.players.contains_key(&player)).unwrap_or(false)
+    };
+    events.into_iter()
+        .filter(|event| match *event {
+            GameEvent::PlayerEaten { victim, eater } => sees(victim) || sees(eater),
+            GameEvent::BallEaten { eater } => sees(eater),
+            GameEvent::PlayerSpawned { id } | GameEvent::PlayerLeft { id } => sees(id),
+        })
+        .collect()
+}
+
+#[test]
+fn test_visible() {
+    use {Player, Cell, Ball};
+
+    let mut state = State::with_config(0, GameConfig { world_size: (10000., 10000.), ..GameConfig::default() });
+    let player = |pos| Player { cells: vec![Cell::new(pos, 10.)], direction: 0., speed: 0., color: (0, 0, 0) };
+    state.players.insert(1, player((1000., 1000.)));
+    state.players.insert(2, player((1300., 1000.)));
+    state.players.insert(3, player((5000., 5000.)));
+    state.balls.push(Ball { id: 0, pos: (1100., 900.), color: (0, 0, 0), size: 1., value: 3. });
+    state.balls.push(Ball { id: 1, pos: (9000., 1000.), color: (0, 0, 0), size: 1., value: 3. });
+
+    let seen = visible(&state, 1);
+    assert_eq!(seen.players.keys().collect::<Vec<_>>(), vec![&1, &2]);
+    assert_eq!(seen.balls.len(), 1);
+    assert_eq!(seen.rng, ::Rng::new(0));
+
+    // Moving sends the balls that came into view, old as they are
+    let before = visible(&state, 1);
+    state.players.get_mut(&1).unwrap().cells[0].pos = (8500., 1000.);
+    let after = visible(&state, 1);
+    assert_eq!(after.balls.iter().map(|ball| ball.id).collect::<Vec<_>>(), vec![1]);
+    assert_eq!(::delta::Delta::between(&before, &after).apply(&before), after);
+
+    // Eaten players see what their eater sees
+    state.players.remove(&1);
+    state.eaten_by.insert(1, 3);
+    state.players.remove(&2);
+    state.eaten_by.insert(2, 3);
+    let seen = visible(&state, 1);
+    assert_eq!(seen.players.keys().collect::<Vec<_>>(), vec![&3]);
+    assert!(seen.balls.is_empty());
+    // But not who else got eaten
+    assert_eq!(seen.eaten_by.into_iter().collect::<Vec<_>>(), vec![(1, 3)]);
+}
+
+#[test]
+fn test_visible_events() {
+    use {Player, Cell};
+
+    let mut state = State::with_config(0, GameConfig { world_size: (10000., 10000.), ..GameConfig::default() });
+    let player = |pos| Player { cells: vec![Cell::new(pos, 10.)], direction: 0., speed: 0., color: (0, 0, 0) };
+    state.players.insert(1, player((1000., 1000.)));
+    state.players.insert(2, player((1300., 1000.)));
+    state.players.insert(3, player((5000., 5000.)));
+    state.players.insert(4, player((8000., 8000.)));
+    let before = visible(&state, 1);
+
+    // 2 went out of view since, 3 is far away
+    state.players.get_mut(&2).unwrap().cells[0].pos = (9000., 1000.);
+    let seen = visible(&state, 1);
+    let events = vec![
+        GameEvent::PlayerSpawned { id: 1 },
+        GameEvent::PlayerLeft { id: 2 },
+        GameEvent::PlayerEaten { victim: 4, eater: 3 },
+        GameEvent::PlayerEaten { victim: 3, eater: 1 },
+        GameEvent::BallEaten { eater: 3 },
+        GameEvent::PlayerSpawned { id: 4 },
+    ];
+    assert_eq!(visible_events(events.clone(), 1, &seen, Some(&before)),
+               vec![events[0].clone(), events[1].clone(), events[3].clone()]);
+    assert_eq!(visible_events(events.clone(), 1, &seen, None), vec![events[0].clone(), events[3].clone()]);
+}
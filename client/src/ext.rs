@@ -2,8 +2,10 @@ use wasm_bindgen::prelude::*;
 
 #[wasm_bindgen(module="./ext")]
 pub extern {
-    pub fn put_char_3(x: f64, y: f64, ch: usize, fr: u8, fg: u8, fb: u8);
+    pub fn put_char_3(x: f64, y: f64, ch: usize, size: f64, fr: u8, fg: u8, fb: u8);
     pub fn put_circle_3(x: f64, y: f64, r: f64, fr: u8, fg: u8, fb: u8, or: u8, og: u8, ob: u8);
+    pub fn put_spiky_circle_3(x: f64, y: f64, r: f64, spikes: usize, fr: u8, fg: u8, fb: u8, or: u8, og: u8, ob: u8);
+    pub fn put_polygon_3(xs: Vec<f64>, ys: Vec<f64>, fr: u8, fg: u8, fb: u8, or: u8, og: u8, ob: u8);
     pub fn put_bg_3(fr: u8, fg: u8, fb: u8);
     pub fn put_line_3(x1: f64, y1: f64, x2: f64, y2: f64, r: f64, fr: u8, fg: u8, fb: u8);
     pub fn clear();
@@ -11,16 +13,43 @@ pub extern {
     pub fn rand() -> f64;
     pub fn atan2(y: f64, x: f64) -> f64;
     pub fn ws_send(msg: Vec<u8>);
+    pub fn save_session(token: String);
 }
 
-pub fn put_char(pos: (f64, f64), ch: usize, col: (u8, u8, u8)) {
-    put_char_3(pos.0, pos.1, ch, col.0, col.1, col.2);
+// Characters are centered on pos, size is the font size in pixels
+pub fn put_char(pos: (f64, f64), ch: usize, size: f64, col: (u8, u8, u8)) {
+    put_char_3(pos.0, pos.1, ch, size, col.0, col.1, col.2);
+}
+
+// Width of a monospace character relative to the font size
+const CHAR_WIDTH: f64 = 0.6;
+
+pub fn text_width(text: &str, size: f64) -> f64 {
+    text.chars().count() as f64 * size * CHAR_WIDTH
+}
+
+// Text starting at pos.0, one put_char at a time
+pub fn put_text(pos: (f64, f64), text: &str, size: f64, col: (u8, u8, u8)) {
+    for (i, ch) in text.chars().enumerate() {
+        let x = pos.0 + (i as f64 + 0.5) * size * CHAR_WIDTH;
+        put_char((x, pos.1), ch as usize, size, col);
+    }
 }
 
 pub fn put_circle(pos: (f64, f64), r: f64, col: (u8, u8, u8), outline: (u8, u8, u8)) {
     put_circle_3(pos.0, pos.1, r, col.0, col.1, col.2, outline.0, outline.1, outline.2);
 }
 
+pub fn put_spiky_circle(pos: (f64, f64), r: f64, spikes: usize, col: (u8, u8, u8), outline: (u8, u8, u8)) {
+    put_spiky_circle_3(pos.0, pos.1, r, spikes, col.0, col.1, col.2, outline.0, outline.1, outline.2);
+}
+
+pub fn put_polygon(points: &[(f64, f64)], col: (u8, u8, u8), outline: (u8, u8, u8)) {
+    let xs = points.iter().map(|p| p.0).collect();
+    let ys = points.iter().map(|p| p.1).collect();
+    put_polygon_3(xs, ys, col.0, col.1, col.2, outline.0, outline.1, outline.2);
+}
+
 pub fn put_bg(col: (u8, u8, u8)) {
     put_bg_3(col.0, col.1, col.2);
 }
@@ -8,12 +8,6 @@ extern crate itertools;
 
 extern crate wasm_bindgen;
 
-#[cfg(feature = "serde_cbor")]
-extern crate serde_cbor as serde_impl;
-
-#[cfg(feature = "serde_json")]
-extern crate serde_json as serde_impl;
-
 use wasm_bindgen::prelude::*;
 
 
@@ -21,8 +15,16 @@ pub mod ext;
 
 use std::sync::Mutex;
 use std::cmp::Ordering;
-
-use agar_backend::{State, IdPlayerCommand, PlayerCommand};
+use std::collections::BTreeMap;
+
+use agar_backend::{State, ClientMessage, IdPlayerCommand, PlayerCommand, GameEvent, Obstacle, GameConfig, WorldMode};
+use agar_backend::names::validate_name;
+use agar_backend::chat::ChatMessage;
+use agar_backend::prediction::Prediction;
+use agar_backend::interpolation::{Interpolation, DEFAULT_DELAY};
+use agar_backend::delta::Receiver;
+use agar_backend::protocol::{self, ServerMessage};
+use agar_backend::view::{self, MIN_ZOOM, MAX_ZOOM};
 use ext::*;
 use itertools::Itertools;
 
@@ -30,6 +32,15 @@ const LINE_SPACE: f64 = 5.;
 const ZOOM_SPEED: f64 = 20.;
 const SIZE_SPEED: f64 = 20.;
 const POS_SPEED: f64 = 10.;
+const VIRUS_SPIKES: usize = 20;
+const FLASH_SPEED: f64 = 2.;
+const LEADERBOARD_TEXT_SIZE: f64 = 16.;
+const LEADERBOARD_MARGIN: f64 = 10.;
+const MIN_NAME_TEXT_SIZE: f64 = 6.; // Names smaller than this aren't drawn
+const CHAT_TEXT_SIZE: f64 = 14.;
+const CHAT_LINES: usize = 8;
+const CHAT_SHOW_TIME: f64 = 10.; // Seconds before a chat message starts fading
+const CHAT_FADE_TIME: f64 = 2.;
 
 lazy_static! {
     static ref SIZE: Mutex<(usize, usize)> = Mutex::new((0, 0));
@@ -37,6 +48,37 @@ lazy_static! {
     static ref ZOOM: Mutex<(f64, f64, Option<(f64, f64)>, f64)> = Mutex::new((0.8, 3., None, 3.)); // (wanted, current, player position, player size)
 
     static ref LAST_TICK: Mutex<Option<f64>> = Mutex::new(None);
+
+    // Victim: eater, from the server's events. Used to follow whoever ate us with the camera
+    static ref FOLLOW: Mutex<BTreeMap<usize, usize>> = Mutex::new(BTreeMap::new());
+    // Fades from 1 to 0 after we ate another player
+    static ref FLASH: Mutex<f64> = Mutex::new(0.);
+
+    static ref LEADERBOARD: Mutex<Vec<(usize, f64)>> = Mutex::new(vec![]); // (id, mass), biggest first
+
+    static ref CHAT: Mutex<Vec<(ChatMessage, f64)>> = Mutex::new(vec![]); // (message, age), oldest first
+
+    // Our commands that the server hasn't applied yet
+    static ref PREDICTION: Mutex<Prediction> = Mutex::new(Prediction::new());
+    // The last few updates, for showing everyone else a little in the past
+    static ref INTERPOLATION: Mutex<Interpolation> = Mutex::new(Interpolation::new(DEFAULT_DELAY));
+    // The last few updates, which the next ones are deltas to
+    static ref RECEIVER: Mutex<Receiver> = Mutex::new(Receiver::new());
+}
+
+// The player the camera should follow, and whether that is us
+fn followed(me_id: usize) -> (usize, bool) {
+    let mut id = me_id;
+    if let Ok(follow) = FOLLOW.lock() {
+        // Bounded in case the chain somehow loops
+        for _ in 0..follow.len() {
+            match follow.get(&id) {
+                Some(eater) => id = *eater,
+                None => break,
+            }
+        }
+    }
+    (id, id == me_id)
 }
 
 #[wasm_bindgen]
@@ -69,26 +111,47 @@ pub fn tick(now: f64) {
         };
 
     draw();
-    let mut me: Option<(Option<(f64, f64)>, f64)> = None;
+    let mut me: Option<(Option<(f64, f64)>, f64, GameConfig)> = None;
     if let Ok(mut state) = STATE.lock() {
-        state.0.tick(dt);
-        let mut me_id = state.1;
-        while let Some(id) = state.0.eaten_by.get(&me_id) {
-            me_id = *id;
+        state.0.advance(dt);
+        // Only the server's events count
+        state.0.take_events();
+
+        if let Ok(mut interpolation) = INTERPOLATION.lock() {
+            interpolation.advance(dt);
         }
 
-        me = Some(state.0.players.get(&me_id).map(|x| (Some(x.pos), x.size)).unwrap_or((None, 10.)));
+        let (me_id, _) = followed(state.1);
+
+        let config = state.0.config.clone();
+        me = Some(match state.0.players.get(&me_id) {
+            Some(player) => (Some(player.center(&config)), player.size(), config),
+            None => (None, 10., config),
+        });
+    }
+
+    if let Ok(mut flash) = FLASH.lock() {
+        *flash = (*flash - dt * FLASH_SPEED).max(0.);
+    }
+
+    if let Ok(mut chat) = CHAT.lock() {
+        for (_, age) in chat.iter_mut() {
+            *age += dt;
+        }
+        chat.retain(|(_, age)| *age < CHAT_SHOW_TIME + CHAT_FADE_TIME);
     }
 
     if let Ok(mut zoom) = ZOOM.lock() {
         zoom.1 = (zoom.1 - zoom.0) * (1. / ZOOM_SPEED).powf(dt) + zoom.0;
 
-        if let Some((pos, size)) = me {
+        if let Some((pos, size, config)) = me {
             zoom.3 = (zoom.3 - size) * (1. / SIZE_SPEED).powf(dt) + size;
-            if let Some((ref mut zx, ref mut zy)) = zoom.2 {
+            if let Some(ref mut camera) = zoom.2 {
                 if let Some((x, y)) = pos {
-                    *zx = (*zx - x) * (1. / POS_SPEED).powf(dt) + x;
-                    *zy = (*zy - y) * (1. / POS_SPEED).powf(dt) + y;
+                    // The short way around in a wrapping world
+                    let (dx, dy) = config.delta((x, y), *camera);
+                    let follow = (1. / POS_SPEED).powf(dt);
+                    *camera = config.keep_inside((x + dx * follow, y + dy * follow), 0.);
                 }
             } else {
                 zoom.2 = pos;
@@ -123,15 +186,96 @@ pub fn mouse_moved(to_x: usize, to_y: usize) {
 
     if let Ok(mut state) = STATE.lock() {
         let cmd = IdPlayerCommand { id: state.1, command: PlayerCommand::SetDirectionAndSpeed(theta, r_sq) };
+        send_command(&mut state.0, cmd);
+    }
 
-        ws_send(serde_impl::to_vec(&cmd).unwrap());
+}
 
-        state.0.do_command(cmd);
 
+#[wasm_bindgen]
+pub fn split() {
+    if let Ok(mut state) = STATE.lock() {
+        let cmd = IdPlayerCommand { id: state.1, command: PlayerCommand::Split };
+        send_command(&mut state.0, cmd);
     }
+}
 
+#[wasm_bindgen]
+pub fn eject_mass() {
+    if let Ok(mut state) = STATE.lock() {
+        let cmd = IdPlayerCommand { id: state.1, command: PlayerCommand::EjectMass };
+        send_command(&mut state.0, cmd);
+    }
 }
 
+// Whether we have been eaten and are spectating. Asked of the state, as we may not have got
+// the events about it, like after resuming a session.
+#[wasm_bindgen]
+pub fn is_dead() -> bool {
+    if let Ok(state) = STATE.lock() {
+        state.0.names.contains_key(&state.1) && !state.0.players.contains_key(&state.1)
+    } else {
+        false
+    }
+}
+
+#[wasm_bindgen]
+pub fn respawn() {
+    if let Ok(mut state) = STATE.lock() {
+        let cmd = IdPlayerCommand { id: state.1, command: PlayerCommand::Respawn };
+        send_command(&mut state.0, cmd);
+    }
+}
+
+// Applies a command right away, and sends it to the server to do the same
+fn send_command(state: &mut State, cmd: IdPlayerCommand) {
+    if let Ok(mut prediction) = PREDICTION.lock() {
+        let seq = prediction.command(state, cmd.clone());
+        ws_send(protocol::encode_client_message(&ClientMessage::Command(seq, cmd)));
+    }
+}
+
+// Sends our name to the server, which then adds our player. Returns why the name isn't
+// allowed, or an empty string if we joined
+#[wasm_bindgen]
+pub fn join(name: String) -> String {
+    match validate_name(&name, &[]) {
+        Ok(name) => {
+            ws_send(protocol::encode_client_message(&ClientMessage::Join(name)));
+            String::new()
+        }
+        Err(e) => e.to_string(),
+    }
+}
+
+// Asks for the player of an earlier connection back, sent before joining
+#[wasm_bindgen]
+pub fn resume(token: String) {
+    ws_send(protocol::encode_client_message(&ClientMessage::Resume(token)));
+}
+
+// Whether we have a player, also when we got one back by resuming
+#[wasm_bindgen]
+pub fn has_joined() -> bool {
+    if let Ok(state) = STATE.lock() {
+        state.0.names.contains_key(&state.1)
+    } else {
+        false
+    }
+}
+
+#[wasm_bindgen]
+pub fn chat(text: String) {
+    ws_send(protocol::encode_client_message(&ClientMessage::Chat(text)));
+}
+
+// How far in the past everyone else is shown, in seconds. Longer hides more network hiccups.
+#[wasm_bindgen]
+pub fn set_interpolation_delay(delay: f64) {
+    if let Ok(mut interpolation) = INTERPOLATION.lock() {
+        interpolation.delay = delay.max(0.);
+    }
+}
 
 #[wasm_bindgen]
 pub fn redraw() {
@@ -141,16 +285,79 @@ pub fn redraw() {
 #[wasm_bindgen]
 pub fn scroll(y: f64) {
     if let Ok(mut zoom) = ZOOM.lock() {
-        zoom.0 = (zoom.0 - y / 20.).max(0.4).min(3.);
+        // The server sends what can be seen at these limits, see agar_backend::view
+        zoom.0 = (zoom.0 - y / 20.).max(MIN_ZOOM).min(MAX_ZOOM);
     }
 }
 
 #[wasm_bindgen]
 pub fn recv_ws(data: Vec<u8>) {
     if let Ok(mut state) = STATE.lock() {
-        match serde_impl::from_slice::<(State, usize)>(&data) {
-            Ok(new_state) => { *state = new_state }
-            Err(e) => { log(format!("Decoding error: {:?}", e)) }
+        let mut receiver = match RECEIVER.lock() {
+            Ok(receiver) => receiver,
+            Err(_) => return,
+        };
+        // Deltas are decoded against the states we got before
+        match protocol::decode_server_message(&data, &receiver) {
+            Ok(ServerMessage { update, id, acked, events, leaderboard, chat: messages, token }) => {
+                if let Some(token) = token {
+                    save_session(token);
+                }
+                let new_state = match receiver.receive(update) {
+                    Some(new_state) => new_state,
+                    None => {
+                        log("Got a delta to a state we don't have".to_string());
+                        return;
+                    }
+                };
+                ws_send(protocol::encode_client_message(&ClientMessage::Ack(new_state.ticks)));
+
+                if let Ok(mut interpolation) = INTERPOLATION.lock() {
+                    interpolation.push(new_state.clone());
+                }
+                // Our own commands the server hasn't got yet are applied again
+                if let Ok(mut prediction) = PREDICTION.lock() {
+                    prediction.reconcile(&mut state.0, new_state, acked);
+                }
+                state.1 = id;
+                handle_events(id, events);
+                if let Ok(mut lb) = LEADERBOARD.lock() {
+                    *lb = leaderboard;
+                }
+                if let Ok(mut chat) = CHAT.lock() {
+                    chat.extend(messages.into_iter().map(|msg| (msg, 0.)));
+                    let too_many = chat.len().saturating_sub(CHAT_LINES);
+                    chat.drain(..too_many);
+                }
+            }
+            Err(e) => { log(format!("Decoding error: {}", e)) }
+        }
+    }
+}
+
+fn handle_events(me_id: usize, events: Vec<GameEvent>) {
+    let follow = FOLLOW.lock();
+    if follow.is_err() { return; }
+    let mut follow = follow.unwrap();
+
+    for event in events {
+        match event {
+            GameEvent::PlayerEaten { victim, eater } => {
+                follow.insert(victim, eater);
+                if eater == me_id {
+                    if let Ok(mut flash) = FLASH.lock() {
+                        *flash = 1.;
+                    }
+                }
+            }
+            GameEvent::PlayerSpawned { id } => {
+                follow.remove(&id);
+            }
+            GameEvent::PlayerLeft { id } => {
+                follow.remove(&id);
+                follow.retain(|_, eater| *eater != id);
+            }
+            GameEvent::BallEaten { .. } => {}
         }
     }
 }
@@ -168,25 +375,33 @@ fn draw() {
 
 
 
+    let flash = FLASH.lock().map(|x| *x).unwrap_or(0.);
+
     if let Ok(state) = STATE.lock() {
-        let mut me_id = state.1;
-        let mut is_me = true;
-        while let Some(id) = state.0.eaten_by.get(&me_id) {
-            me_id = *id;
-            is_me = false;
+        // Everyone else as they were a moment ago, moving smoothly
+        let mut view = state.0.clone();
+        if let Ok(interpolation) = INTERPOLATION.lock() {
+            interpolation.apply(&mut view, state.1);
         }
+        let state = (view, state.1);
+
+        let (me_id, is_me) = followed(state.1);
 
         if is_me {
-            put_bg((255, 255, 255));
+            let fade = 255 - (flash * 55.) as u8;
+            put_bg((fade, 255, fade));
         } else {
             put_bg((25, 25, 25));
         }
 
-        let real_pos = state.0.players.get(&me_id).map(|x| x.pos).unwrap_or((0., 0.));
+        let config = &state.0.config;
+        // Without a player the server sends what's around the middle of the world
+        let middle = (config.world_size.0 / 2., config.world_size.1 / 2.);
+        let real_pos = state.0.players.get(&me_id).map(|x| x.center(config)).unwrap_or(middle);
         let my_pos = my_pos.unwrap_or(real_pos);
 
 
-        let zoom = 0.010 * zoom_mul / (my_size.sqrt() + 2.) * (size.0 + size.1) as f64;
+        let zoom = view::zoom(zoom_mul, my_size, (size.0 as f64, size.1 as f64));
 
         // Grid lines
         let x_scroll = (my_pos.0 / LINE_SPACE - ((my_pos.0 / LINE_SPACE) as i64) as f64) * LINE_SPACE;
@@ -215,63 +430,217 @@ fn draw() {
         }
 
 
-        // Draw west wall
-        put_line(
-            (-(my_pos.0 * zoom) + size.0 as f64 / 2., 0.),
-            (-(my_pos.0 * zoom) + size.0 as f64 / 2., size.1 as f64),
-            2.,
-            if is_me { (100, 100, 100) }
-                else { (200, 200, 200) }
-        );
-        // Draw east wall
-        put_line(
-            (-((my_pos.0 - state.0.size.0) * zoom) + size.0 as f64 / 2., 0.),
-            (-((my_pos.0 - state.0.size.0) * zoom) + size.0 as f64 / 2., size.1 as f64),
-            2.,
-            if is_me { (100, 100, 100) }
-                else { (200, 200, 200) }
-        );
+        if config.world_mode == WorldMode::Walled {
+            draw_walls(config.world_size, my_pos, zoom, (size.0 as f64, size.1 as f64), is_me);
+        }
 
-        // Draw north wall
-        put_line(
-            (           0., -(my_pos.1 * zoom) + size.1 as f64 / 2.),
-            (size.0 as f64, -(my_pos.1 * zoom) + size.1 as f64 / 2.),
-            2.,
-            if is_me { (100, 100, 100) }
-                else { (200, 200, 200) }
-        );
-        // Draw east wall
-        put_line(
-            (           0., -((my_pos.1 - state.0.size.1) * zoom) + size.1 as f64 / 2.),
-            (size.0 as f64, -((my_pos.1 - state.0.size.1) * zoom) + size.1 as f64 / 2.),
-            2.,
-            if is_me { (100, 100, 100) }
-                else { (200, 200, 200) }
-        );
+        // Where something at pos shows up on the screen. A wrapping world repeats in every
+        // direction, so something of the given radius close to where the copies meet can show
+        // up more than once. It is drawn where it is closest to us, and again for each
+        // neighbouring copy of the world it reaches into the screen from.
+        let to_screen = |pos: (f64, f64)| {
+            ((pos.0 - my_pos.0) * zoom + size.0 as f64 / 2.,
+             (pos.1 - my_pos.1) * zoom + size.1 as f64 / 2.)
+        };
+        let half_screen = (size.0 as f64 / 2. / zoom, size.1 as f64 / 2. / zoom);
+        let images = |pos: (f64, f64), radius: f64| -> Vec<(f64, f64)> {
+            if config.world_mode == WorldMode::Walled {
+                return vec![to_screen(pos)];
+            }
+            let (dx, dy) = config.delta(my_pos, pos);
+            let mut images = vec![to_screen((my_pos.0 + dx, my_pos.1 + dy))];
+            for ox in -1..2 {
+                for oy in -1..2 {
+                    let (x, y) = (dx + ox as f64 * config.world_size.0, dy + oy as f64 * config.world_size.1);
+                    if (ox, oy) != (0, 0) && x.abs() < half_screen.0 + radius && y.abs() < half_screen.1 + radius {
+                        images.push(to_screen((my_pos.0 + x, my_pos.1 + y)));
+                    }
+                }
+            }
+            images
+        };
+        // Every image of a shape, moved along with its first point
+        let shifted = |points: &[(f64, f64)]| -> Vec<Vec<(f64, f64)>> {
+            let first = to_screen(points[0]);
+            let radius = points.iter()
+                .map(|p| ((p.0 - points[0].0).powi(2) + (p.1 - points[0].1).powi(2)).sqrt())
+                .fold(0., f64::max);
+            images(points[0], radius).into_iter()
+                .map(|image| points.iter().map(|p| {
+                    let p = to_screen(*p);
+                    (p.0 - first.0 + image.0, p.1 - first.1 + image.1)
+                }).collect())
+                .collect()
+        };
 
+        let obstacle_col = if is_me { (150, 150, 160) } else { (70, 70, 80) };
+        let obstacle_outline = if is_me { (0, 0, 0) } else { (255, 255, 255) };
+        for obstacle in &state.0.obstacles {
+            match obstacle {
+                Obstacle::Circle { center, radius } => {
+                    for image in images(*center, *radius) {
+                        put_circle(image, radius * zoom, obstacle_col, obstacle_outline);
+                    }
+                }
+                Obstacle::Rect { min, max } => {
+                    for corners in shifted(&[*min, (max.0, min.1), *max, (min.0, max.1)]) {
+                        put_polygon(&corners, obstacle_col, obstacle_outline);
+                    }
+                }
+                Obstacle::Polygon { points } => {
+                    if points.is_empty() { continue }
+                    for points in shifted(points) {
+                        put_polygon(&points, obstacle_col, obstacle_outline);
+                    }
+                }
+            }
+        }
 
         for ball in &state.0.balls {
-            put_circle(
-                ((ball.pos.0 - my_pos.0) * zoom + size.0 as f64 / 2.,
-                 (ball.pos.1 - my_pos.1) * zoom + size.1 as f64 / 2.),
-                zoom,
-                ball.color,
-                if is_me { (0, 0, 0) }
-                    else { (255, 255, 255) }
-            );
+            for image in images(ball.pos, ball.size) {
+                put_circle(
+                    image,
+                    ball.size * zoom,
+                    ball.color,
+                    if is_me { (0, 0, 0) }
+                        else { (255, 255, 255) }
+                );
+            }
         }
 
-        let sorted_players = &state.0.players.values()
-                .sorted_by(|x, y| PartialOrd::partial_cmp(&x.size, &y.size).unwrap_or(Ordering::Less));
-        for player in sorted_players {
-            put_circle(
-                ((player.pos.0 - my_pos.0) * zoom + size.0 as f64 / 2.,
-                 (player.pos.1 - my_pos.1) * zoom + size.1 as f64 / 2.),
-                player.show_size * zoom,
-                player.color,
-                if is_me { (0, 0, 0) }
-                    else { (255, 255, 255) }
-            );
+        for ejected in &state.0.ejected {
+            for image in images(ejected.pos, ejected.size) {
+                put_circle(
+                    image,
+                    ejected.size * zoom,
+                    ejected.color,
+                    if is_me { (0, 0, 0) }
+                        else { (255, 255, 255) }
+                );
+            }
+        }
+
+        // Cells smaller than a virus are drawn under it
+        let mut blobs: Vec<(f64, Option<(u8, u8, u8)>, (f64, f64), f64, &str)> = vec![]; // (size, color or virus, screen pos, shown size, name)
+        for (id, player) in &state.0.players {
+            let name = state.0.names.get(id).map(|x| x.as_str()).unwrap_or("");
+            for cell in &player.cells {
+                for image in images(cell.pos, cell.show_size) {
+                    blobs.push((cell.size, Some(player.color), image, cell.show_size, name));
+                }
+            }
+        }
+        for virus in &state.0.viruses {
+            for image in images(virus.pos, virus.size) {
+                blobs.push((virus.size, None, image, virus.size, ""));
+            }
         }
+        let sorted_blobs = blobs.into_iter()
+                .sorted_by(|x, y| PartialOrd::partial_cmp(&x.0, &y.0).unwrap_or(Ordering::Less));
+
+        for (_, color, screen_pos, show_size, name) in sorted_blobs {
+            let outline = if is_me { (0, 0, 0) } else { (255, 255, 255) };
+
+            match color {
+                Some(color) => put_circle(screen_pos, show_size * zoom, color, outline),
+                None => put_spiky_circle(screen_pos, show_size * zoom, VIRUS_SPIKES, (50, 220, 50), outline),
+            }
+
+            // Shrink long names to fit inside the cell
+            let mut text_size = show_size * zoom * 0.5;
+            let width = text_width(name, text_size);
+            if width > show_size * zoom * 1.6 {
+                text_size *= show_size * zoom * 1.6 / width;
+            }
+            if !name.is_empty() && text_size >= MIN_NAME_TEXT_SIZE {
+                let x = screen_pos.0 - text_width(name, text_size) / 2.;
+                put_text((x, screen_pos.1), name, text_size, outline);
+            }
+        }
+
+        draw_leaderboard(&state.0, size.0 as f64, state.1, is_me);
+        draw_chat(size.1 as f64, is_me);
     }
 }
+
+fn draw_leaderboard(state: &State, width: f64, me_id: usize, is_me: bool) {
+    let leaderboard = LEADERBOARD.lock();
+    if leaderboard.is_err() { return; }
+    let leaderboard = leaderboard.unwrap();
+
+    let text_col = if is_me { (0, 0, 0) } else { (255, 255, 255) };
+    let my_col = (200, 50, 50);
+
+    for (i, (id, mass)) in leaderboard.iter().enumerate() {
+        let name = match state.names.get(id) {
+            Some(name) if !name.is_empty() => name.clone(),
+            _ => format!("#{}", id),
+        };
+        let line = format!("{}. {} {}", i + 1, name, *mass as u64);
+        let x = width - LEADERBOARD_MARGIN - text_width(&line, LEADERBOARD_TEXT_SIZE);
+        let y = LEADERBOARD_MARGIN + (i + 1) as f64 * LEADERBOARD_TEXT_SIZE * 1.2;
+
+        put_text((x, y), &line, LEADERBOARD_TEXT_SIZE, if *id == me_id { my_col } else { text_col });
+    }
+}
+
+// Bottom left, above the chat input. Old messages fade into the background.
+fn draw_chat(height: f64, is_me: bool) {
+    let chat = CHAT.lock();
+    if chat.is_err() { return; }
+    let chat = chat.unwrap();
+
+    let text_col = if is_me { (0., 0., 0.) } else { (255., 255., 255.) };
+    let bg_col = if is_me { (255., 255., 255.) } else { (25., 25., 25.) };
+
+    for (i, (msg, age)) in chat.iter().rev().enumerate() {
+        let fade = ((age - CHAT_SHOW_TIME) / CHAT_FADE_TIME).max(0.).min(1.);
+        let col = (
+            (text_col.0 + (bg_col.0 - text_col.0) * fade) as u8,
+            (text_col.1 + (bg_col.1 - text_col.1) * fade) as u8,
+            (text_col.2 + (bg_col.2 - text_col.2) * fade) as u8,
+        );
+
+        let name = if msg.name.is_empty() { format!("#{}", msg.id) } else { msg.name.clone() };
+        let line = format!("{}: {}", name, msg.text);
+        let y = height - LEADERBOARD_MARGIN - CHAT_TEXT_SIZE * 3. - i as f64 * CHAT_TEXT_SIZE * 1.2;
+
+        put_text((LEADERBOARD_MARGIN, y), &line, CHAT_TEXT_SIZE, col);
+    }
+}
+
+fn draw_walls(world_size: (f64, f64), my_pos: (f64, f64), zoom: f64, screen: (f64, f64), is_me: bool) {
+    // Draw west wall
+    put_line(
+        (-(my_pos.0 * zoom) + screen.0 / 2., 0.),
+        (-(my_pos.0 * zoom) + screen.0 / 2., screen.1),
+        2.,
+        if is_me { (100, 100, 100) }
+            else { (200, 200, 200) }
+    );
+    // Draw east wall
+    put_line(
+        (-((my_pos.0 - world_size.0) * zoom) + screen.0 / 2., 0.),
+        (-((my_pos.0 - world_size.0) * zoom) + screen.0 / 2., screen.1),
+        2.,
+        if is_me { (100, 100, 100) }
+            else { (200, 200, 200) }
+    );
+
+    // Draw north wall
+    put_line(
+        (      0., -(my_pos.1 * zoom) + screen.1 / 2.),
+        (screen.0, -(my_pos.1 * zoom) + screen.1 / 2.),
+        2.,
+        if is_me { (100, 100, 100) }
+            else { (200, 200, 200) }
+    );
+    // Draw south wall
+    put_line(
+        (      0., -((my_pos.1 - world_size.1) * zoom) + screen.1 / 2.),
+        (screen.0, -((my_pos.1 - world_size.1) * zoom) + screen.1 / 2.),
+        2.,
+        if is_me { (100, 100, 100) }
+            else { (200, 200, 200) }
+    );
+}
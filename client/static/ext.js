@@ -8,7 +8,8 @@ function get_size() {
     return [w, h];
 }
 
-export function put_char_3(x, y, ch, fr, fg, fb) {
+export function put_char_3(x, y, ch, size, fr, fg, fb) {
+    ctx.font = `${size}px monospace`;
     ctx.fillStyle = `rgb(${fr & 255},${fg & 255},${fb & 255})`;
     ctx.fillText(String.fromCharCode(ch), x, y);
 }
@@ -36,6 +37,40 @@ export function put_circle_3(x, y, r, fr, fg, fb, or, og, ob) {
     ctx.stroke();
 }
 
+export function put_spiky_circle_3(x, y, r, spikes, fr, fg, fb, or, og, ob) {
+    if (x + r < 0 ||
+        y + r < 0 ||
+        x - r > get_size()[0] ||
+        y - r > get_size()[1] )
+    {
+        return;
+    }
+
+    ctx.fillStyle = `rgb(${fr & 255},${fg & 255},${fb & 255})`;
+    ctx.strokeStyle = `rgb(${or & 255},${og & 255},${ob & 255})`;
+    ctx.beginPath();
+    for (let i = 0; i < spikes * 2; i++) {
+        let angle = i / (spikes * 2) * 2 * Math.PI;
+        let dist = i % 2 === 0 ? r : r * 0.9;
+        ctx.lineTo(x + Math.cos(angle) * dist, y + Math.sin(angle) * dist);
+    }
+    ctx.closePath();
+    ctx.fill();
+    ctx.stroke();
+}
+
+export function put_polygon_3(xs, ys, fr, fg, fb, or, og, ob) {
+    ctx.fillStyle = `rgb(${fr & 255},${fg & 255},${fb & 255})`;
+    ctx.strokeStyle = `rgb(${or & 255},${og & 255},${ob & 255})`;
+    ctx.beginPath();
+    for (let i = 0; i < xs.length; i++) {
+        ctx.lineTo(xs[i], ys[i]);
+    }
+    ctx.closePath();
+    ctx.fill();
+    ctx.stroke();
+}
+
 export function put_line_3(x1, y1, x2, y2, r, fr, fg, fb) {
     ctx.strokeStyle = `rgb(${fr & 255},${fg & 255},${fb & 255})`;
     ctx.lineWidth = r;
@@ -65,3 +100,8 @@ export function atan2(y, x) {
 export function ws_send(data) {
     ws.send(new Uint8Array(data).buffer);
 }
+
+// Kept to get our player back after reconnecting
+export function save_session(token) {
+    localStorage.setItem("session", token);
+}
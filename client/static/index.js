@@ -28,11 +28,24 @@ agar.then(module => {
         ws = new WebSocket("ws://" + window.location.hostname + ":6969");
         ws.binaryType = "arraybuffer";
 
+        ws.onopen = () => {
+            let token = localStorage.getItem("session");
+            if (token !== null) {
+                module.resume(token);
+            }
+        }
+
         ws.onmessage = msg => {
             module.recv_ws(Array.from(new Uint8Array(msg.data)));
         }
         module.start(width, height);
 
+        // ?delay=0.3 shows other players further in the past, for bad connections
+        let params = new URLSearchParams(window.location.search);
+        if (params.has("delay")) {
+            module.set_interpolation_delay(parseFloat(params.get("delay")));
+        }
+
         document.body.addEventListener("mousemove", event => {
             module.mouse_moved(event.x, event.y);
         });
@@ -52,9 +65,80 @@ agar.then(module => {
             module.scroll(event.deltaY);
         });
 
+        let join = document.getElementById("join");
+        let name = document.getElementById("name");
+        let join_error = document.getElementById("join-error");
+        let joined = false;
+
+        function try_join() {
+            let error = module.join(name.value);
+            join_error.textContent = error;
+            if (error === "") {
+                joined = true;
+                join.style.display = "none";
+            }
+        }
+        document.getElementById("play").addEventListener("click", event => {
+            try_join();
+        });
+        name.addEventListener("keydown", event => {
+            if (event.key === "Enter") {
+                try_join();
+            }
+            // Don't split or eject while typing
+            event.stopPropagation();
+        });
+
+        // Enter opens the chat input and sends what's typed, escape closes it
+        let chat = document.getElementById("chat");
+        chat.addEventListener("keydown", event => {
+            if (event.key === "Enter") {
+                if (chat.value !== "") {
+                    module.chat(chat.value);
+                }
+                chat.value = "";
+                chat.style.display = "none";
+                chat.blur();
+            }
+            if (event.key === "Escape") {
+                chat.value = "";
+                chat.style.display = "none";
+                chat.blur();
+            }
+            event.stopPropagation();
+        });
+
+        let death = document.getElementById("death");
+        document.getElementById("respawn").addEventListener("click", event => {
+            module.respawn();
+        });
+
+        document.body.addEventListener("keydown", event => {
+            if (event.key === "Enter" && joined) {
+                if (module.is_dead()) {
+                    module.respawn();
+                } else {
+                    chat.style.display = "block";
+                    chat.focus();
+                }
+            }
+            if (event.key === " ") {
+                module.split();
+            }
+            if (event.key === "w") {
+                module.eject_mass();
+            }
+        });
+
         function ticker() {
             let d = new Date();
             module.tick(d.getTime() / 1000);
+            // Resuming an earlier session gets our player back without joining
+            if (!joined && module.has_joined()) {
+                joined = true;
+                join.style.display = "none";
+            }
+            death.style.display = joined && module.is_dead() ? "block" : "none";
             requestAnimationFrame(ticker);
         }
         requestAnimationFrame(ticker);
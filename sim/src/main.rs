@@ -0,0 +1,210 @@
+// Runs a game of bots with no networking and prints how it went, for comparing rule changes.
+//
+//     sim --bots 20 --seed 1 --duration 600 --config game.toml --format csv
+//
+// Every --interval seconds of game time a sample is printed, as a CSV row or a JSON object.
+
+extern crate agar_backend;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate toml;
+
+use std::collections::BTreeMap;
+use std::env::args;
+use std::fs::File;
+use std::io::Read;
+use std::process::exit;
+
+use agar_backend::{State, GameConfig, GameEvent, Arena, TICK_DT};
+use agar_backend::bots::Bots;
+
+#[derive(Debug, PartialEq)]
+enum Format {
+    Csv,
+    Json,
+}
+
+struct Options {
+    bots: usize,
+    seed: u64,
+    duration: f64,
+    interval: f64,
+    config: GameConfig,
+    arena: Arena,
+    format: Format,
+}
+
+// What the game looked like at the end of an interval, and what happened during it
+#[derive(Serialize, Debug)]
+struct Sample {
+    time: f64,
+    alive: usize,
+    pellets: usize,
+    total_mass: f64,
+    min_mass: f64,
+    median_mass: f64,
+    p90_mass: f64,
+    max_mass: f64,
+    balls_eaten: usize,
+    players_eaten: usize,
+    // Of the players eaten during the interval, in seconds
+    mean_survival: Option<f64>,
+}
+
+const CSV_HEADER: &str = "time,alive,pellets,total_mass,min_mass,median_mass,p90_mass,max_mass,balls_eaten,players_eaten,mean_survival";
+
+impl Sample {
+    fn csv_row(&self) -> String {
+        format!("{},{},{},{},{},{},{},{},{},{},{}",
+                self.time, self.alive, self.pellets, self.total_mass,
+                self.min_mass, self.median_mass, self.p90_mass, self.max_mass,
+                self.balls_eaten, self.players_eaten,
+                self.mean_survival.map(|x| x.to_string()).unwrap_or_default())
+    }
+}
+
+fn main() {
+    let options = match parse_args() {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("Usage: sim [--bots N] [--seed N] [--duration SECS] [--interval SECS] [--config FILE] [--arena FILE] [--format csv|json]");
+            exit(1);
+        }
+    };
+
+    let samples = simulate(&options);
+
+    match options.format {
+        Format::Csv => {
+            println!("{}", CSV_HEADER);
+            for sample in &samples {
+                println!("{}", sample.csv_row());
+            }
+        }
+        Format::Json => {
+            println!("{}", serde_json::to_string_pretty(&samples).expect("Can't jsonise the samples!"));
+        }
+    }
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options {
+        bots: 20,
+        seed: 0,
+        duration: 300.,
+        interval: 10.,
+        config: GameConfig::default(),
+        arena: Arena::default(),
+        format: Format::Csv,
+    };
+
+    let mut args = args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--bots" => options.bots = value()?.parse().map_err(|_| "--bots needs a number")?,
+            "--seed" => options.seed = value()?.parse().map_err(|_| "--seed needs a number")?,
+            "--duration" => options.duration = value()?.parse().map_err(|_| "--duration needs a number of seconds")?,
+            "--interval" => options.interval = value()?.parse().map_err(|_| "--interval needs a number of seconds")?,
+            "--config" => options.config = load_toml(&value()?)?,
+            "--arena" => options.arena = load_toml(&value()?)?,
+            "--format" => {
+                options.format = match value()?.as_str() {
+                    "csv" => Format::Csv,
+                    "json" => Format::Json,
+                    other => return Err(format!("Unknown format {}", other)),
+                }
+            }
+            _ => return Err(format!("Unknown argument {}", arg)),
+        }
+    }
+
+    if options.interval <= 0. {
+        return Err("--interval has to be positive".to_string());
+    }
+    Ok(options)
+}
+
+fn load_toml<T: serde::de::DeserializeOwned>(path: &str) -> Result<T, String> {
+    let mut text = String::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut text))
+        .map_err(|e| format!("Can't read {}: {:?}", path, e))?;
+
+    toml::from_str(&text).map_err(|e| format!("Can't parse {}: {}", path, e))
+}
+
+fn simulate(options: &Options) -> Vec<Sample> {
+    let mut state = State::with_config(options.seed, options.config.clone());
+    state.obstacles = options.arena.obstacles.clone();
+    state.fill_balls();
+
+    let mut bots = Bots::new();
+    bots.keep_population(&mut state, options.bots, |state| {
+        (1..).find(|id| !state.names.contains_key(id)).unwrap()
+    });
+
+    let ticks_per_sample = ((options.interval / TICK_DT).round() as u64).max(1);
+    let total_ticks = (options.duration / TICK_DT).round() as u64;
+
+    let mut spawned_at: BTreeMap<usize, u64> = BTreeMap::new(); // Id: tick
+    for id in state.players.keys() {
+        spawned_at.insert(*id, 0);
+    }
+
+    let mut samples = vec![];
+    let (mut balls_eaten, mut players_eaten) = (0, 0);
+    let mut survivals = vec![];
+    for tick in 1..=total_ticks {
+        bots.command_all(&mut state);
+        state.tick(TICK_DT);
+
+        for event in state.take_events() {
+            match event {
+                GameEvent::BallEaten { .. } => balls_eaten += 1,
+                GameEvent::PlayerEaten { victim, .. } => {
+                    players_eaten += 1;
+                    if let Some(spawned) = spawned_at.remove(&victim) {
+                        survivals.push((tick - spawned) as f64 * TICK_DT);
+                    }
+                }
+                GameEvent::PlayerSpawned { id } => { spawned_at.insert(id, tick); }
+                GameEvent::PlayerLeft { id } => { spawned_at.remove(&id); }
+            }
+        }
+
+        if tick % ticks_per_sample == 0 || tick == total_ticks {
+            samples.push(sample(&state, tick as f64 * TICK_DT, balls_eaten, players_eaten, &survivals));
+            balls_eaten = 0;
+            players_eaten = 0;
+            survivals.clear();
+        }
+    }
+    samples
+}
+
+fn sample(state: &State, time: f64, balls_eaten: usize, players_eaten: usize, survivals: &[f64]) -> Sample {
+    let mut masses: Vec<f64> = state.players.values().map(|player| player.mass()).collect();
+    masses.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    let percentile = |p: f64| {
+        if masses.is_empty() { 0. } else { masses[((masses.len() - 1) as f64 * p).round() as usize] }
+    };
+
+    Sample {
+        time,
+        alive: masses.len(),
+        pellets: state.balls.len(),
+        total_mass: masses.iter().sum(),
+        min_mass: percentile(0.),
+        median_mass: percentile(0.5),
+        p90_mass: percentile(0.9),
+        max_mass: percentile(1.),
+        balls_eaten,
+        players_eaten,
+        mean_survival: if survivals.is_empty() { None } else { Some(survivals.iter().sum::<f64>() / survivals.len() as f64) },
+    }
+}
@@ -0,0 +1,95 @@
+// Plays a game recorded with `ws-server --record` again and tells how it ended up.
+//
+//     replay game.rec --tick 6400 --out state.bin
+//
+// Without --tick the game runs up to the last recorded input. --out writes the whole final
+// State, serialized with the same CBOR or JSON as the recording and snapshots. That's not what
+// clients get, which is agar_backend::protocol's binary updates of only what they can see.
+
+extern crate agar_backend;
+
+#[cfg(feature = "serde_cbor")]
+extern crate serde_cbor as serde_impl;
+
+#[cfg(feature = "serde_json")]
+extern crate serde_json as serde_impl;
+
+use std::env::args;
+use std::fs::File;
+use std::io::{BufReader, Write};
+use std::process::exit;
+
+use agar_backend::TICK_DT;
+use agar_backend::replay::{RecordingHeader, Input, replay, read_frame, RECORDING_VERSION};
+
+fn main() {
+    let mut args = args().skip(1);
+
+    let mut path = None;
+    let mut until = None;
+    let mut out = None;
+    while let Some(arg) = args.next() {
+        if arg == "--tick" {
+            until = Some(args.next().and_then(|x| x.parse::<u64>().ok()).expect("--tick needs a number"));
+            continue;
+        }
+        if arg == "--out" {
+            out = Some(args.next().expect("--out needs a file"));
+            continue;
+        }
+        path = Some(arg);
+    }
+
+    let path = match path {
+        Some(path) => path,
+        None => {
+            eprintln!("Usage: replay <recording> [--tick N] [--out FILE]");
+            exit(1);
+        }
+    };
+
+    let (header, inputs) = load_recording(&path).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        exit(1);
+    });
+    eprintln!("Replaying {} inputs from {}", inputs.len(), path);
+
+    let state = replay(&header, inputs, until);
+
+    println!("After {} ticks ({:.1} seconds):", state.ticks, state.ticks as f64 * TICK_DT);
+    for (id, name) in &state.names {
+        match state.players.get(id) {
+            Some(player) => println!("{:5} {:16} {:10.1}", id, name, player.mass()),
+            None => println!("{:5} {:16} {:>10}", id, name, "dead"),
+        }
+    }
+
+    if let Some(out) = out {
+        let bytes = serde_impl::to_vec(&state).expect("Can't serialize the state!");
+        File::create(&out)
+            .and_then(|mut f| f.write_all(&bytes))
+            .unwrap_or_else(|e| {
+                eprintln!("Can't write {}: {:?}", out, e);
+                exit(1);
+            });
+    }
+}
+
+fn load_recording(path: &str) -> Result<(RecordingHeader, Vec<(u64, Input)>), String> {
+    let mut file = BufReader::new(File::open(path).map_err(|e| format!("Can't open {}: {:?}", path, e))?);
+    let mut next_frame = || read_frame(&mut file).map_err(|e| format!("Can't read {}: {:?}", path, e));
+
+    let header: RecordingHeader = match next_frame()? {
+        Some(frame) => serde_impl::from_slice(&frame).map_err(|e| format!("Bad header: {:?}", e))?,
+        None => return Err(format!("{} is empty", path)),
+    };
+    if header.version != RECORDING_VERSION {
+        return Err(format!("{} is from version {}, this is version {}", path, header.version, RECORDING_VERSION));
+    }
+
+    let mut inputs = vec![];
+    while let Some(frame) = next_frame()? {
+        inputs.push(serde_impl::from_slice(&frame).map_err(|e| format!("Bad input: {:?}", e))?);
+    }
+    Ok((header, inputs))
+}
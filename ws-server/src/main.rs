@@ -6,6 +6,8 @@ extern crate tungstenite;
 extern crate tokio;
 extern crate futures;
 extern crate agar_backend;
+extern crate toml;
+extern crate tokio_signal;
 
 #[cfg(feature = "serde_cbor")]
 extern crate serde_cbor as serde_impl;
@@ -17,31 +19,176 @@ extern crate serde_json as serde_impl;
 
 use std::net::{SocketAddr, IpAddr};
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::{Instant, Duration};
-use std::io::{Error, ErrorKind};
+use std::io::{Error, ErrorKind, Read, Write, BufWriter};
+use std::fs::{self, File};
 use std::env::args;
 use std::thread;
+use std::collections::{BTreeMap, VecDeque};
+use std::process::exit;
 
 use tokio::net::TcpListener;
-use tokio::timer::Interval;
+use tokio::timer::{Interval, Delay};
 use tokio_tungstenite::accept_async;
 use tungstenite::Message;
 
-use futures::{Future, Stream, Sink};
+use futures::{future, Future, Stream, Sink};
 use futures::sync::mpsc::unbounded;
 
-use agar_backend::{State, IdPlayerCommand};
+use agar_backend::{State, ClientMessage, GameConfig, GameEvent, Arena, random_seed};
+use agar_backend::names::validate_name;
+use agar_backend::bots::Bots;
+use agar_backend::replay::{RecordingHeader, Input, write_frame};
+use agar_backend::delta;
+use agar_backend::protocol::{self, ServerMessage};
+use agar_backend::view;
+use agar_backend::snapshot::{Snapshot, SnapshotVersion, SNAPSHOT_VERSION};
+use agar_backend::chat::{ChatMessage, RateLimiter, clean_message, CHAT_BURST, CHAT_PER_SEC};
+
+// How many players are sent in the leaderboard
+const LEADERBOARD_SIZE: usize = 10;
+
+// How many events and chat messages are kept for connections that haven't sent them yet
+const EVENT_LOG_SIZE: usize = 1024;
+const CHAT_LOG_SIZE: usize = 64;
+
+// How long players from a snapshot have to reconnect after the server resumed, at least
+const RESUME_GRACE: Duration = Duration::from_secs(60);
 
 lazy_static! {
     static ref STATE: Arc<Mutex<State>> = Arc::new(Mutex::new(State::new()));
     static ref PLAYER_ADDR_ID: Mutex<Vec<(SocketAddr, usize)>> = Mutex::new(Vec::new());
+    // Players whose connection closed but who are kept alive for the grace period, in case
+    // their client resumes its session. By the address of the closed connection.
+    static ref DISCONNECTED: Mutex<Vec<(SocketAddr, usize)>> = Mutex::new(Vec::new());
+    // Names containing any of these can't be used
+    static ref DENY_LIST: Mutex<Vec<String>> = Mutex::new(Vec::new());
+    static ref EVENT_LOG: Mutex<Log<GameEvent>> = Mutex::new(Log::new(EVENT_LOG_SIZE));
+    static ref CHAT_LOG: Mutex<Log<ChatMessage>> = Mutex::new(Log::new(CHAT_LOG_SIZE));
+    // Where every input is written with --record, see agar_backend::replay
+    static ref RECORDING: Mutex<Option<BufWriter<File>>> = Mutex::new(None);
+    // Session token: player id. Clients keep their token to get their player back when
+    // reconnecting, even to a restarted server.
+    static ref SESSIONS: Mutex<BTreeMap<String, usize>> = Mutex::new(BTreeMap::new());
+}
+
+// Set on ctrl-c, for the state manager to save everything and stop
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+// The player a connection controls, which changes if the client resumes an older session
+struct Session {
+    id: usize,
+    token: String,
+    acked: u64, // The last command received, sent back for the client's prediction
+    received: Option<u64>, // The ticks of the newest update the client got
+}
+
+// Where and how often the game is saved
+struct Snapshots {
+    path: Option<String>,
+    every: Duration,
+}
+
+// The latest events taken from the state or chat messages, numbered so that every connection
+// can send each one once
+struct Log<T> {
+    next: u64, // Number of the next item to be pushed
+    size: usize,
+    items: VecDeque<T>,
+}
+
+impl<T: Clone> Log<T> {
+    fn new(size: usize) -> Log<T> {
+        Log { next: 0, size, items: VecDeque::new() }
+    }
+
+    fn push(&mut self, items: Vec<T>) {
+        for item in items {
+            if self.items.len() == self.size {
+                self.items.pop_front();
+            }
+            self.items.push_back(item);
+            self.next += 1;
+        }
+    }
+
+    // Items from number `from` and on, and the number to continue from next time
+    fn since(&self, from: u64) -> (Vec<T>, u64) {
+        let first = self.next - self.items.len() as u64;
+        let skip = from.max(first) - first;
+        (self.items.iter().skip(skip as usize).cloned().collect(), self.next)
+    }
 }
 
 fn main() {
-    let mut args = args();
+    let mut args = args().skip(1);
 
     let mut addr: SocketAddr = ([127, 0, 0, 1], 6969).into();
-    if let Some(arg) = args.nth(1) {
+    let mut grace = Duration::from_secs(0);
+    let mut min_population = 0;
+    let mut config = None;
+    let mut arena = None;
+    let mut seed = None;
+    let mut record = None;
+    let mut snapshots = Snapshots { path: None, every: Duration::from_secs(60) };
+    let mut resume = false;
+    while let Some(arg) = args.next() {
+        if arg == "--deny-list" {
+            let path = args.next().expect("--deny-list needs a file");
+            let words = load_deny_list(&path).expect("Can't load deny list");
+
+            eprintln!("Denying {} words in names", words.len());
+            if let Ok(mut deny_list) = DENY_LIST.lock() {
+                *deny_list = words;
+            }
+            continue;
+        }
+        if arg == "--bots" {
+            min_population = args.next().and_then(|x| x.parse::<usize>().ok()).expect("--bots needs a number of players");
+            continue;
+        }
+        if arg == "--grace" {
+            let secs = args.next().and_then(|x| x.parse::<u64>().ok()).expect("--grace needs a number of seconds");
+            grace = Duration::from_secs(secs);
+            continue;
+        }
+        if arg == "--arena" {
+            let path = args.next().expect("--arena needs a file");
+            let loaded = load_arena(&path).expect("Can't load arena");
+
+            eprintln!("Using {} obstacles from {}", loaded.obstacles.len(), path);
+            arena = Some(loaded);
+            continue;
+        }
+        if arg == "--config" {
+            let path = args.next().expect("--config needs a file");
+            config = Some(load_config(&path).expect("Can't load config"));
+
+            eprintln!("Using config from {}", path);
+            continue;
+        }
+        if arg == "--seed" {
+            seed = Some(args.next().and_then(|x| x.parse::<u64>().ok()).expect("--seed needs a number"));
+            continue;
+        }
+        if arg == "--record" {
+            record = Some(args.next().expect("--record needs a file"));
+            continue;
+        }
+        if arg == "--snapshot" {
+            snapshots.path = Some(args.next().expect("--snapshot needs a file"));
+            continue;
+        }
+        if arg == "--snapshot-every" {
+            let secs = args.next().and_then(|x| x.parse::<u64>().ok()).expect("--snapshot-every needs a number of seconds");
+            snapshots.every = Duration::from_secs(secs);
+            continue;
+        }
+        if arg == "--resume" {
+            resume = true;
+            continue;
+        }
         if let Ok(x) = arg.parse::<SocketAddr>() {
             addr = x;
         }
@@ -50,11 +197,69 @@ fn main() {
         }
     }
 
+    // Players from the snapshot who haven't reconnected yet
+    let mut unclaimed = vec![];
+    let mut bots = Bots::new();
+    let snapshot = if resume {
+        let path = snapshots.path.as_ref().expect("--resume needs a --snapshot file to resume from");
+        match load_snapshot(path) {
+            Ok(snapshot) => Some(snapshot),
+            Err(e) => {
+                eprintln!("Not resuming: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let header = match snapshot {
+        Some(snapshot) => {
+            eprintln!("Resuming after {} ticks with {} players", snapshot.state.ticks, snapshot.state.names.len());
+            unclaimed = snapshot.humans();
+
+            let mut state = snapshot.state;
+            // Anything given again overrides what was saved
+            if let Some(config) = config {
+                state.config = config;
+            }
+            if let Some(arena) = arena {
+                state.obstacles = arena.obstacles;
+            }
+            bots = snapshot.bots;
+            if let Ok(mut sessions) = SESSIONS.lock() {
+                *sessions = snapshot.sessions;
+            }
+            RecordingHeader::from_state(&state)
+        }
+        None => {
+            let obstacles = arena.map(|arena| arena.obstacles).unwrap_or_default();
+            RecordingHeader::new(seed.unwrap_or_else(random_seed), config.unwrap_or_default(), obstacles)
+        }
+    };
+    if let Ok(mut state) = STATE.lock() {
+        *state = header.start_state();
+    }
+    if let Some(path) = record {
+        start_recording(&path, &header).expect("Can't start recording");
+        eprintln!("Recording to {}", path);
+    }
+
     eprintln!("Starting WebSocket server on {}", addr);
 
     let server = TcpListener::bind(&addr).expect("Can't make server");
 
-    thread::spawn(run_state_manager);
+    thread::spawn(move || run_state_manager(min_population, bots, snapshots, unclaimed, grace.max(RESUME_GRACE)));
+
+    let shutdown = tokio_signal::ctrl_c()
+        .flatten_stream()
+        .take(1)
+        .for_each(|_| {
+            eprintln!("Shutting down");
+            SHUTDOWN.store(true, Ordering::SeqCst);
+            Ok(())
+        })
+        .map_err(|e| eprintln!("Can't listen for ctrl-c: {:?}", e));
 
     let f = server.incoming()
         .map_err(|e| {
@@ -68,30 +273,75 @@ fn main() {
                 .map(move |ws| (ws, addr))
                 .map_err(|e| Error::new(ErrorKind::Other, e))
         })
-        .for_each(|(ws_stream, addr)| {
+        .for_each(move |(ws_stream, addr)| {
             println!("Websocket connection from {:?}", addr);
 
             let (sink, stream) = ws_stream.split();
 
             let (mut sender, recv) = unbounded();
 
-            let mut id = 0;
-            if let Ok(mut state) = STATE.lock() {
-                if let Ok(mut player_addr_id) = PLAYER_ADDR_ID.lock() {
-                    let highest_id = player_addr_id.iter().map(|(_, id)| *id).max().unwrap_or(0);
-                    id = highest_id + 1;
-                    player_addr_id.push((addr, id));
-                    state.add_player(id);
+            let session = Arc::new(Mutex::new(connect_player(addr)));
+            let connected = Arc::new(AtomicBool::new(true));
 
-                    println!("Added player {:?}", id);
-                }
-            }
+            let mut next_event = EVENT_LOG.lock().map(|log| log.next).unwrap_or(0);
+            let mut next_chat = CHAT_LOG.lock().map(|log| log.next).unwrap_or(0);
+            let connected_at = Instant::now();
+            let mut chat_limiter = RateLimiter::new(CHAT_BURST, CHAT_PER_SEC, 0.);
+            let mut sent_token = None;
+            let mut updates = delta::Sender::new();
+            let mut last_seen = None;
 
+            let still_connected = connected.clone();
+            let pinger_session = session.clone();
             let pinger = Interval::new(Instant::now(), Duration::from_millis(100))
+                    .take_while(move |_| Ok(still_connected.load(Ordering::SeqCst)))
                     .for_each(move |_| {
+                        let (id, token, acked) = match pinger_session.lock() {
+                            Ok(session) => {
+                                if let Some(ticks) = session.received {
+                                    updates.ack(ticks);
+                                }
+                                (session.id, session.token.clone(), session.acked)
+                            }
+                            Err(_) => return Ok(()),
+                        };
+                        // Sent again only after resuming another session
+                        let new_token = if sent_token.as_ref() == Some(&token) { None } else { Some(token.clone()) };
+                        sent_token = Some(token);
+
                         if let Ok(state) = STATE.lock() {
-                            let json = serde_impl::to_vec(&(&*state, id)).expect("Can't jsonise the state!");
-                            sender.start_send(Message::Binary(json));
+                            let events = match EVENT_LOG.lock() {
+                                Ok(log) => {
+                                    let (events, next) = log.since(next_event);
+                                    next_event = next;
+                                    events
+                                }
+                                Err(_) => vec![],
+                            };
+
+                            let chat = match CHAT_LOG.lock() {
+                                Ok(log) => {
+                                    let (chat, next) = log.since(next_chat);
+                                    next_chat = next;
+                                    chat
+                                }
+                                Err(_) => vec![],
+                            };
+
+                            let leaderboard = state.leaderboard(LEADERBOARD_SIZE);
+
+                            // Only what the client can see, and only what changed of that since
+                            // the client's last acknowledged update
+                            let seen = view::visible(&state, id);
+                            let events = view::visible_events(events, id, &seen, last_seen.as_ref());
+                            let update = updates.update(&seen);
+                            last_seen = Some(seen);
+                            let base = update.base().and_then(|ticks| updates.base(ticks));
+                            let message = ServerMessage { update, id, acked, events, leaderboard, chat, token: new_token };
+                            match protocol::encode_server_message(&message, base) {
+                                Ok(bytes) => { sender.start_send(Message::Binary(bytes)); }
+                                Err(e) => eprintln!("Not sending an update to {}: {}", id, e),
+                            }
                         }
 
                         Ok(())
@@ -101,25 +351,63 @@ fn main() {
             let send = recv.fold(
                 sink,
                 |mut sink, msg| {
-                    sink.start_send(msg).expect("Can't send!");
-                    Ok(sink)
+                    sink.start_send(msg).map(|_| sink).map_err(|_| ())
                 })
                 .map(|_| ());
 
+            let disconnect_session = session.clone();
             let stream = stream
                     .for_each(move |msg| {
-                        if let Message::Binary(json) = msg {
-                            if let Ok(cmd) = serde_impl::from_slice::<IdPlayerCommand>(&json) {
-                                if cmd.id == id {
-                                    if let Ok(mut state) = STATE.lock() {
-                                        state.do_command(cmd);
+                        if let Message::Binary(bytes) = msg {
+                            let id = match session.lock() {
+                                Ok(session) => session.id,
+                                Err(_) => return Ok(()),
+                            };
+                            match protocol::decode_client_message(&bytes) {
+                                Ok(ClientMessage::Join(name)) => {
+                                    join_player(id, &name);
+                                }
+                                Ok(ClientMessage::Ack(ticks)) => {
+                                    if let Ok(mut session) = session.lock() {
+                                        session.received = Some(ticks);
+                                    }
+                                }
+                                Ok(ClientMessage::Resume(token)) => {
+                                    if let Ok(mut session) = session.lock() {
+                                        resume_session(addr, &mut session, token);
                                     }
                                 }
+                                Ok(ClientMessage::Command(seq, cmd)) => {
+                                    // Only commands that were applied are acknowledged, the
+                                    // client keeps predicting the others
+                                    if cmd.id == id {
+                                        if let Ok(mut state) = STATE.lock() {
+                                            apply(&mut state, Input::Command(cmd));
+                                        }
+                                        if let Ok(mut session) = session.lock() {
+                                            session.acked = session.acked.max(seq);
+                                        }
+                                    }
+                                }
+                                Ok(ClientMessage::Chat(text)) => {
+                                    if chat_limiter.allow(secs(connected_at.elapsed())) {
+                                        send_chat(id, &text);
+                                    } else {
+                                        println!("Player {:?} is chatting too fast", id);
+                                    }
+                                }
+                                Err(_) => {}
                             }
                         }
                         Ok(())
                     })
-                    .map_err(|_| ());
+                    .then(move |_| -> Result<(), ()> {
+                        connected.store(false, Ordering::SeqCst);
+                        if let Ok(session) = disconnect_session.lock() {
+                            disconnect_player(addr, session.id, grace);
+                        }
+                        Ok(())
+                    });
 
             tokio::spawn(send);
             tokio::spawn(stream);
@@ -133,20 +421,350 @@ fn main() {
             eprintln!("Error: {:?}", e);
         });
 
-    tokio::run(f);
+    tokio::run(future::lazy(move || {
+        tokio::spawn(shutdown);
+        f
+    }));
+}
+
+// Gives a new connection an id. The player is added when the client joins, or the client
+// gets an earlier player back by resuming its session.
+fn connect_player(addr: SocketAddr) -> Session {
+    let state = STATE.lock().unwrap();
+    let mut player_addr_id = PLAYER_ADDR_ID.lock().unwrap();
+    let disconnected = DISCONNECTED.lock().unwrap();
+    let mut sessions = SESSIONS.lock().unwrap();
+
+    let id = free_id(&state, &player_addr_id, &disconnected);
+    player_addr_id.push((addr, id));
+
+    println!("Connected player {:?}", id);
+    Session { id, token: new_session(&mut sessions, id), acked: 0, received: None }
+}
+
+fn new_session(sessions: &mut BTreeMap<String, usize>, id: usize) -> String {
+    let token = format!("{:016x}{:016x}", random_seed(), random_seed());
+    sessions.insert(token.clone(), id);
+    token
+}
+
+// Switches a connection that hasn't joined yet over to the player of an earlier session, if
+// that player is still in the game and nobody else is connected as them
+fn resume_session(addr: SocketAddr, session: &mut Session, token: String) {
+    let state = STATE.lock().unwrap();
+    let mut player_addr_id = PLAYER_ADDR_ID.lock().unwrap();
+    let mut disconnected = DISCONNECTED.lock().unwrap();
+    let mut sessions = SESSIONS.lock().unwrap();
+
+    let id = match sessions.get(&token) {
+        Some(id) => *id,
+        None => return,
+    };
+    if id == session.id
+        || state.names.contains_key(&session.id)
+        || !state.names.contains_key(&id)
+        || player_addr_id.iter().any(|(_, x)| *x == id)
+    {
+        return;
+    }
+
+    // Keeps the player from being removed after the grace period
+    disconnected.retain(|(_, x)| *x != id);
+    sessions.remove(&session.token);
+    for entry in player_addr_id.iter_mut() {
+        if *entry == (addr, session.id) {
+            entry.1 = id;
+        }
+    }
+
+    println!("Player {:?} resumed as {:?}", session.id, id);
+    session.id = id;
+    session.token = token;
 }
 
-fn run_state_manager() {
+// The lowest id not used by a connection, a player or a bot
+fn free_id(state: &State, player_addr_id: &[(SocketAddr, usize)], disconnected: &[(SocketAddr, usize)]) -> usize {
+    let mut id = 1;
+    while player_addr_id.iter().any(|(_, x)| *x == id)
+        || disconnected.iter().any(|(_, x)| *x == id)
+        || state.players.contains_key(&id)
+        || state.names.contains_key(&id)
+    {
+        id += 1;
+    }
+    id
+}
+
+// Adds the player of a connection once, when its client sends a name. Invalid names are
+// replaced with no name at all.
+fn join_player(id: usize, name: &str) {
+    let name = {
+        let deny_list = DENY_LIST.lock().unwrap();
+        match validate_name(name, &deny_list) {
+            Ok(name) => name,
+            Err(e) => {
+                println!("Player {:?} tried to join with a bad name: {}", id, e);
+                String::new()
+            }
+        }
+    };
+
+    if let Ok(mut state) = STATE.lock() {
+        if joined(&state, id) {
+            return;
+        }
+        apply(&mut state, Input::Join { id, name });
+    }
+    println!("Added player {:?}", id);
+}
+
+// Passes a message on to everyone, from players who have joined
+fn send_chat(id: usize, text: &str) {
+    let text = match clean_message(text) {
+        Some(text) => text,
+        None => return,
+    };
+
+    let name = match STATE.lock() {
+        Ok(state) => match state.names.get(&id) {
+            Some(name) => name.clone(),
+            None => return,
+        },
+        Err(_) => return,
+    };
+
+    if let Ok(mut log) = CHAT_LOG.lock() {
+        log.push(vec![ChatMessage { id, name, text }]);
+    }
+}
+
+// Removes the player of a closed connection, after the grace period if there is one
+fn disconnect_player(addr: SocketAddr, id: usize, grace: Duration) {
+    println!("Websocket connection from {:?} closed", addr);
+
+    if let Ok(mut player_addr_id) = PLAYER_ADDR_ID.lock() {
+        player_addr_id.retain(|&(a, x)| !(a == addr && x == id));
+    }
+
+    // Nothing to remove or keep for connections that never joined, only their session
+    if !STATE.lock().map(|state| joined(&state, id)).unwrap_or(false) {
+        if let Ok(mut sessions) = SESSIONS.lock() {
+            sessions.retain(|_, x| *x != id);
+        }
+        return;
+    }
+
+    if grace == Duration::from_secs(0) {
+        if let Ok(mut state) = STATE.lock() {
+            leave(&mut state, id);
+        }
+        println!("Removed player {:?}", id);
+        return;
+    }
+
+    if let Ok(mut disconnected) = DISCONNECTED.lock() {
+        disconnected.push((addr, id));
+    }
+
+    let remove = Delay::new(Instant::now() + grace)
+            .map(move |_| {
+                // Unless the player's session was resumed in the meantime
+                let still_gone = {
+                    let mut disconnected = DISCONNECTED.lock().unwrap();
+                    match disconnected.iter().position(|&(a, x)| a == addr && x == id) {
+                        Some(idx) => { disconnected.remove(idx); true }
+                        None => false,
+                    }
+                };
+
+                if still_gone {
+                    if let Ok(mut state) = STATE.lock() {
+                        leave(&mut state, id);
+                    }
+                    println!("Removed player {:?}", id);
+                }
+            })
+            .map_err(|_| ());
+
+    tokio::spawn(remove);
+}
+
+// Everything that changes the game from outside goes through here, so it can be recorded.
+// Only call this with the state locked, so inputs are recorded in the order they're applied.
+fn apply(state: &mut State, input: Input) {
+    record(state.ticks, &[input.clone()]);
+    input.apply(state);
+}
+
+fn joined(state: &State, id: usize) -> bool {
+    state.names.contains_key(&id) || state.players.contains_key(&id)
+}
+
+// Removes a player for good, so their session can't be resumed anymore
+fn leave(state: &mut State, id: usize) {
+    if joined(state, id) {
+        apply(state, Input::Leave { id });
+    }
+    if let Ok(mut sessions) = SESSIONS.lock() {
+        sessions.retain(|_, x| *x != id);
+    }
+}
+
+fn record(ticks: u64, inputs: &[Input]) {
+    if let Ok(mut recording) = RECORDING.lock() {
+        let failed = match recording.as_mut() {
+            Some(file) => inputs.iter().any(|input| {
+                let bytes = serde_impl::to_vec(&(ticks, input)).expect("Can't serialize an input!");
+                write_frame(file, &bytes).is_err()
+            }),
+            None => false,
+        };
+        if failed {
+            eprintln!("Can't write to the recording, not recording anymore");
+            *recording = None;
+        }
+    }
+}
+
+fn start_recording(path: &str, header: &RecordingHeader) -> Result<(), String> {
+    let mut file = BufWriter::new(File::create(path).map_err(|e| format!("Can't create {}: {:?}", path, e))?);
+
+    let bytes = serde_impl::to_vec(header).map_err(|e| format!("Can't serialize the header: {:?}", e))?;
+    write_frame(&mut file, &bytes).map_err(|e| format!("Can't write to {}: {:?}", path, e))?;
+
+    if let Ok(mut recording) = RECORDING.lock() {
+        *recording = Some(file);
+    }
+    Ok(())
+}
+
+// One word per line
+fn load_deny_list(path: &str) -> Result<Vec<String>, String> {
+    let mut text = String::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut text))
+        .map_err(|e| format!("Can't read {}: {:?}", path, e))?;
+
+    Ok(text.lines().map(|line| line.trim().to_string()).filter(|line| !line.is_empty()).collect())
+}
+
+fn load_arena(path: &str) -> Result<Arena, String> {
+    let mut text = String::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut text))
+        .map_err(|e| format!("Can't read {}: {:?}", path, e))?;
+
+    toml::from_str(&text).map_err(|e| format!("Can't parse {}: {}", path, e))
+}
+
+// The config is sent to the clients as part of the state
+fn load_config(path: &str) -> Result<GameConfig, String> {
+    let mut text = String::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut text))
+        .map_err(|e| format!("Can't read {}: {:?}", path, e))?;
+
+    toml::from_str(&text).map_err(|e| format!("Can't parse {}: {}", path, e))
+}
+
+fn load_snapshot(path: &str) -> Result<Snapshot, String> {
+    let mut bytes = vec![];
+    File::open(path)
+        .and_then(|mut f| f.read_to_end(&mut bytes))
+        .map_err(|e| format!("Can't read {}: {:?}", path, e))?;
+
+    let version: SnapshotVersion = serde_impl::from_slice(&bytes).map_err(|e| format!("Can't parse {}: {:?}", path, e))?;
+    if version.version != SNAPSHOT_VERSION {
+        return Err(format!("{} is from version {}, this is version {}", path, version.version, SNAPSHOT_VERSION));
+    }
+    serde_impl::from_slice(&bytes).map_err(|e| format!("Can't parse {}: {:?}", path, e))
+}
+
+// Writes next to the old snapshot first, so there always is a whole one
+fn save_snapshot(path: &str, snapshot: &Snapshot) -> Result<(), String> {
+    let bytes = serde_impl::to_vec(snapshot).map_err(|e| format!("Can't serialize the snapshot: {:?}", e))?;
+
+    let tmp = format!("{}.tmp", path);
+    File::create(&tmp)
+        .and_then(|mut f| f.write_all(&bytes).and_then(|_| f.sync_all()))
+        .map_err(|e| format!("Can't write {}: {:?}", tmp, e))?;
+    fs::rename(&tmp, path).map_err(|e| format!("Can't replace {}: {:?}", path, e))
+}
+
+fn secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 * 1e-9
+}
+
+// Runs the game, with bots keeping at least min_population players in it. Players in
+// unclaimed are removed after resume_grace unless they reconnect.
+fn run_state_manager(min_population: usize, mut bots: Bots, snapshots: Snapshots, mut unclaimed: Vec<usize>, resume_grace: Duration) {
+    let started = Instant::now();
+    let mut last_snapshot = Instant::now();
+
     let state_manager = Interval::new(Instant::now(), Duration::from_millis(75))
-            .fold(None, |last, now| {
+            .fold(None, move |last, now| {
                 match last {
                     None => Ok(Some(now)),
                     Some(last) => {
-                        let since_last = now.duration_since(last);
-                        let dt = since_last.as_secs() as f64 + since_last.subsec_nanos() as f64 * 1e-9;
+                        let dt = secs(now.duration_since(last));
+                        let shutdown = SHUTDOWN.load(Ordering::SeqCst);
+                        let mut snapshot = None;
 
                         if let Ok(mut state) = STATE.lock() {
-                            state.tick(dt);
+                            if !unclaimed.is_empty() && started.elapsed() > resume_grace {
+                                // Players who came back are connected, or disconnected again
+                                // and treated like anyone else
+                                let mut back: Vec<usize> = PLAYER_ADDR_ID.lock().unwrap().iter().map(|(_, id)| *id).collect();
+                                back.extend(DISCONNECTED.lock().unwrap().iter().map(|(_, id)| *id));
+                                for id in unclaimed.drain(..).filter(|id| !back.contains(id)) {
+                                    leave(&mut state, id);
+                                    println!("Removed player {:?}, who didn't come back", id);
+                                }
+                            }
+
+                            // The bots apply their inputs themselves
+                            let mut inputs = bots.keep_population(&mut state, min_population, |state| {
+                                let player_addr_id = PLAYER_ADDR_ID.lock().unwrap();
+                                let disconnected = DISCONNECTED.lock().unwrap();
+                                free_id(state, &player_addr_id, &disconnected)
+                            });
+                            inputs.extend(bots.command_all(&mut state));
+                            record(state.ticks, &inputs);
+
+                            state.advance(dt);
+
+                            if let Ok(mut recording) = RECORDING.lock() {
+                                if let Some(file) = recording.as_mut() {
+                                    let _ = file.flush();
+                                }
+                            }
+
+                            // Not every pellet eaten anywhere, clients have no use for those.
+                            // Each client hears about the rest in its view, see view::visible_events
+                            let events = state.take_events().into_iter()
+                                .filter(|event| !matches!(event, GameEvent::BallEaten { .. }))
+                                .collect();
+                            if let Ok(mut log) = EVENT_LOG.lock() {
+                                log.push(events);
+                            }
+
+                            if snapshots.path.is_some() && (shutdown || last_snapshot.elapsed() >= snapshots.every) {
+                                let sessions = SESSIONS.lock().map(|sessions| sessions.clone()).unwrap_or_default();
+                                snapshot = Some(Snapshot::new(state.clone(), bots.clone(), sessions));
+                            }
+                        }
+
+                        // Written without holding up the game
+                        if let (Some(snapshot), Some(path)) = (snapshot, snapshots.path.as_ref()) {
+                            match save_snapshot(path, &snapshot) {
+                                Ok(()) => println!("Saved a snapshot to {}", path),
+                                Err(e) => eprintln!("Can't save a snapshot: {}", e),
+                            }
+                            last_snapshot = Instant::now();
+                        }
+
+                        if shutdown {
+                            exit(0);
                         }
 
                         Ok(Some(now))